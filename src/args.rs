@@ -0,0 +1,111 @@
+pub const USAGE: &str = "usage: service-manager [check] [--profile <name>...] [<filepath>]
+       service-manager schema
+       service-manager import compose [--out <filepath>] [<compose file>]
+       service-manager export systemd [--out <dir>] [--force] [--profile <name>...] [<filepath>]
+  check            validate the config without starting the services
+  schema           print the JSON Schema of the config file
+  import compose   convert the services of a docker-compose file to a config
+  export systemd   write a systemd user unit for every service
+the file can be a TOML config or a Procfile
+the config file can also be set with the environment variable SERVICE_MANAGER_CONFIG_PATH";
+
+const SUBCOMMANDS: &[&str] = &["check", "schema", "import", "export"];
+
+pub enum Command {
+    /// Show the services in the terminal UI
+    Run,
+    /// Validate the config and report all problems
+    Check,
+    /// Print the JSON Schema of the config file
+    Schema,
+    /// Convert a docker-compose file to a config
+    ImportCompose,
+    /// Write systemd units for the services
+    ExportSystemd,
+}
+
+pub struct Args {
+    pub command: Command,
+    /// The config file, or the file to import
+    pub path: Option<String>,
+    /// Where the result of an import or export is written to
+    pub out: Option<String>,
+    /// Only services without profiles or with one of these are loaded
+    pub profiles: Vec<String>,
+    /// Whether an export overwrites files that it didn't write
+    pub force: bool,
+}
+
+impl Args {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut args = args.peekable();
+
+        let command = match args.next_if(|arg| SUBCOMMANDS.contains(&arg.as_str())) {
+            Some(subcommand) if subcommand == "check" => Command::Check,
+            Some(subcommand) if subcommand == "schema" => Command::Schema,
+            Some(subcommand) if subcommand == "import" => match args.next().as_deref() {
+                Some("compose") => Command::ImportCompose,
+                Some(format) => return Err(format!("can't import from `{}`", format)),
+                None => return Err("missing the format to import from".to_string()),
+            },
+            Some(subcommand) if subcommand == "export" => match args.next().as_deref() {
+                Some("systemd") => Command::ExportSystemd,
+                Some(format) => return Err(format!("can't export to `{}`", format)),
+                None => return Err("missing the format to export to".to_string()),
+            },
+            _ => Command::Run,
+        };
+
+        let mut parsed = Args {
+            command,
+            path: None,
+            out: None,
+            profiles: Vec::new(),
+            force: false,
+        };
+
+        while let Some(arg) = args.next() {
+            if let Some(profiles) = option_value("--profile", &arg, &mut args)? {
+                parsed.profiles.extend(split_list(&profiles));
+            } else if let Some(out) = option_value("--out", &arg, &mut args)? {
+                parsed.out = Some(out);
+            } else if arg == "--force" {
+                parsed.force = true;
+            } else if arg.starts_with("--") {
+                return Err(format!("unknown option `{}`", arg));
+            } else if parsed.path.is_none() {
+                parsed.path = Some(arg);
+            } else {
+                return Err(format!("unexpected argument `{}`", arg));
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// The value of the option `name` if `arg` is either `--name value` or `--name=value`
+fn option_value(
+    name: &str,
+    arg: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<Option<String>, String> {
+    if arg == name {
+        args.next()
+            .map(Some)
+            .ok_or_else(|| format!("missing value for `{}`", name))
+    } else {
+        Ok(arg
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('='))
+            .map(str::to_string))
+    }
+}
+
+/// `--profile a,b` is the same as `--profile a --profile b`
+fn split_list(list: &str) -> impl Iterator<Item = String> + '_ {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+}
@@ -0,0 +1,322 @@
+use crate::model::config::Limit;
+use crate::model::Usage;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// The period of `cpu.max`, the default of the kernel
+const CPU_PERIOD_MICROS: u64 = 100_000;
+
+/// The child cgroup the service manager moves itself into
+const MANAGER_CGROUP: &str = "manager";
+
+/// How long the killed processes are waited for when the cgroups are removed
+const REMOVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The cgroup v2 of this instance of the service manager, in which every service gets a child
+/// cgroup. It is created in the cgroup the service manager was started in, so that other
+/// instances started from the same terminal have their own. The controllers are only enabled if
+/// the cgroup is delegated to us, otherwise the child cgroups are still used to kill all processes
+/// of a service and for the CPU accounting.
+#[derive(Debug)]
+pub struct Cgroups {
+    /// The cgroup the service manager was started in and moves back to on exit
+    parent: PathBuf,
+    dir: PathBuf,
+    memory: bool,
+    cpu: bool,
+    /// The controllers that were enabled in the parent by us and are disabled again on exit
+    enabled: Vec<&'static str>,
+}
+
+impl Cgroups {
+    /// Sets up the cgroup of the service manager, `None` if cgroups v2 aren't used, the service
+    /// manager is in the root cgroup or its cgroup isn't writable
+    pub fn init() -> Option<Cgroups> {
+        let parent = match own_cgroup() {
+            Some(parent) => parent,
+            None => {
+                info!("Not using cgroups, there is no cgroup v2");
+                return None;
+            }
+        };
+
+        // the root cgroup belongs to the system
+        if !parent.join("cgroup.type").exists() {
+            info!("Not using cgroups, the service manager is in the root cgroup");
+            return None;
+        }
+
+        // controllers can only be enabled for the child cgroups if the cgroup has no processes
+        // itself, so the service manager moves into a child cgroup as well
+        let dir = parent.join(format!("service-manager-{}", std::process::id()));
+        let manager = dir.join(MANAGER_CGROUP);
+        let moved = create_cgroup(&dir)
+            .and_then(|_| create_cgroup(&manager))
+            .and_then(|_| fs::write(manager.join("cgroup.procs"), std::process::id().to_string()));
+
+        if let Err(err) = moved {
+            info!(error = %err, cgroup = %parent.display(), "Not using cgroups, the cgroup isn't writable");
+            let _ = fs::remove_dir(&manager);
+            let _ = fs::remove_dir(&dir);
+            return None;
+        }
+
+        let read_controllers = |file: &str| {
+            fs::read_to_string(parent.join(file))
+                .map(|controllers| {
+                    controllers
+                        .split_whitespace()
+                        .map(str::to_string)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+        let available = read_controllers("cgroup.controllers");
+        let already_enabled = read_controllers("cgroup.subtree_control");
+
+        // a controller has to be enabled in the parent to be available in our cgroup
+        let mut enabled = Vec::new();
+        let mut enable = |controller: &'static str| {
+            if !already_enabled.iter().any(|c| c == controller) {
+                let is_enabled = available.iter().any(|c| c == controller)
+                    && write_subtree_control(&parent, '+', controller).is_ok();
+                if !is_enabled {
+                    return false;
+                }
+                enabled.push(controller);
+            }
+
+            write_subtree_control(&dir, '+', controller).is_ok()
+        };
+        let memory = enable("memory");
+        let cpu = enable("cpu");
+
+        info!(cgroup = %dir.display(), memory, cpu, "Using cgroups");
+
+        Some(Cgroups {
+            parent,
+            dir,
+            memory,
+            cpu,
+            enabled,
+        })
+    }
+
+    /// Creates an empty cgroup for a run of the service and sets its limits. The messages are
+    /// about the limits that can't be enforced.
+    pub fn create(
+        &self,
+        name: &str,
+        memory_max: Option<&Limit>,
+        cpu_max: Option<f64>,
+    ) -> io::Result<(PathBuf, Vec<String>)> {
+        let dir = self.dir.join(cgroup_name(name));
+        let mut messages = Vec::new();
+
+        // a new cgroup starts the accounting from zero. If processes of the last run are still
+        // alive, the cgroup can't be removed and is used again.
+        let _ = fs::remove_dir(&dir);
+        create_cgroup(&dir)?;
+
+        if let Some(memory_max) = memory_max {
+            if self.memory {
+                // the loader made sure that the limit is valid
+                let value = match memory_max.value() {
+                    Ok(Some(bytes)) => bytes.to_string(),
+                    _ => "max".to_string(),
+                };
+                fs::write(dir.join("memory.max"), value)?;
+            } else {
+                messages.push(
+                    "memory_max isn't enforced, the memory controller isn't available".to_string(),
+                );
+            }
+        }
+
+        if let Some(cpu_max) = cpu_max {
+            if self.cpu {
+                let quota = (cpu_max * CPU_PERIOD_MICROS as f64) as u64;
+                fs::write(
+                    dir.join("cpu.max"),
+                    format!("{} {}", quota, CPU_PERIOD_MICROS),
+                )?;
+            } else {
+                messages
+                    .push("cpu_max isn't enforced, the cpu controller isn't available".to_string());
+            }
+        }
+
+        Ok((dir, messages))
+    }
+
+    /// Kills the processes that are left in the cgroups of the services and removes the cgroups.
+    /// The cgroup of the service manager is put back the way it was found.
+    pub fn remove_all(&self) {
+        let cgroups = fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.file_name().as_bytes().starts_with(b"service-"))
+            .map(|entry| entry.path())
+            .collect::<Vec<_>>();
+
+        for cgroup in cgroups.iter().filter(|cgroup| is_populated(cgroup)) {
+            let _ = kill(cgroup);
+        }
+
+        // a cgroup can only be removed once its processes are gone
+        let deadline = Instant::now() + REMOVE_TIMEOUT;
+        for cgroup in &cgroups {
+            while fs::remove_dir(cgroup).is_err() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        // the controllers have to be disabled before the service manager can move back. That
+        // fails if another instance still uses them, then the service manager stays where it is.
+        for controller in &self.enabled {
+            let _ = write_subtree_control(&self.parent, '-', controller);
+        }
+
+        let moved_back = fs::write(
+            self.parent.join("cgroup.procs"),
+            std::process::id().to_string(),
+        );
+        match moved_back {
+            Ok(()) => {
+                let _ = fs::remove_dir(self.dir.join(MANAGER_CGROUP));
+                let _ = fs::remove_dir(&self.dir);
+            }
+            Err(err) => {
+                info!(error = %err, cgroup = %self.parent.display(), "Failed to move back to the cgroup")
+            }
+        }
+    }
+}
+
+/// Makes the command move its process into the cgroup before it is executed, so that everything
+/// it starts is in the cgroup as well
+pub fn move_into(cmd: &mut Command, cgroup: &Path) -> io::Result<()> {
+    // the path has to be created before the fork
+    let procs = CString::new(cgroup.join("cgroup.procs").as_os_str().as_bytes())?;
+
+    // SAFETY: the closure runs in the forked child, where only async-signal-safe functions may
+    // be called. It only calls `open`, `write` and `close` and doesn't allocate.
+    unsafe {
+        cmd.pre_exec(move || {
+            let fd = libc::open(procs.as_ptr(), libc::O_WRONLY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // `0` is the process that writes it
+            let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+            let result = match written {
+                -1 => Err(io::Error::last_os_error()),
+                _ => Ok(()),
+            };
+
+            libc::close(fd);
+
+            result
+        });
+    }
+
+    Ok(())
+}
+
+/// Kills every process in the cgroup. Returns `false` if the kernel doesn't support
+/// `cgroup.kill`.
+pub fn kill(cgroup: &Path) -> io::Result<bool> {
+    match fs::write(cgroup.join("cgroup.kill"), "1") {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// What the processes in the cgroup used so far
+pub fn usage(cgroup: &Path) -> Usage {
+    let read_number = |file: &str| {
+        fs::read_to_string(cgroup.join(file))
+            .ok()
+            .and_then(|content| content.trim().parse::<u64>().ok())
+    };
+
+    let cpu = fs::read_to_string(cgroup.join("cpu.stat"))
+        .ok()
+        .and_then(|stat| {
+            stat.lines()
+                .find_map(|line| line.strip_prefix("usage_usec "))
+                .and_then(|usage| usage.trim().parse().ok())
+        })
+        .map(Duration::from_micros);
+
+    Usage {
+        memory: read_number("memory.current"),
+        memory_peak: read_number("memory.peak"),
+        cpu,
+    }
+}
+
+/// Whether there are processes in the cgroup
+fn is_populated(cgroup: &Path) -> bool {
+    fs::read_to_string(cgroup.join("cgroup.events")).map_or(true, |events| {
+        events.lines().any(|line| line == "populated 1")
+    })
+}
+
+/// The directory of the cgroup v2 the service manager is in
+fn own_cgroup() -> Option<PathBuf> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+
+    // the mount point is usually `/sys/fs/cgroup`, or `/sys/fs/cgroup/unified` on hybrid systems
+    let mounts = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    let mount_point = mounts.lines().find_map(|line| {
+        let (mount, filesystem) = line.split_once(" - ")?;
+        if filesystem.split_whitespace().next()? != "cgroup2" {
+            return None;
+        }
+        mount.split_whitespace().nth(4)
+    })?;
+
+    Some(Path::new(mount_point).join(path.trim_start_matches('/')))
+}
+
+/// Enables (`+`) or disables (`-`) a controller for the child cgroups
+fn write_subtree_control(cgroup: &Path, change: char, controller: &str) -> io::Result<()> {
+    fs::write(
+        cgroup.join("cgroup.subtree_control"),
+        format!("{}{}", change, controller),
+    )
+}
+
+fn create_cgroup(dir: &Path) -> io::Result<()> {
+    match fs::create_dir(dir) {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+        result => result,
+    }
+}
+
+/// The name of the cgroup of a service, with the characters that aren't allowed in file names
+/// replaced
+fn cgroup_name(service: &str) -> String {
+    let name = service
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+
+    format!("service-{}", name)
+}
@@ -1,24 +1,112 @@
-use crate::controller::{StdioSendBuf, STDIO_SEND_BUF_SIZE};
+use crate::controller::{cgroup, StdioSendBuf, STDIO_SEND_BUF_SIZE};
 use crate::model::{ServiceStatus, SmError, SmResult};
-use std::io::{Read, Write};
-use std::process::{Child, ChildStderr, ChildStdout};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
 use std::sync::mpsc::TryRecvError;
 use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
 use std::{io, thread};
 use tracing::{error, info};
 
-enum ChildAction {
-    Ignore,
-    Kill,
+/// How long a service has to exit after it was asked to stop before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the output of a hook is waited for after it exited. Processes that the hook started
+/// in the background can keep its output open.
+const HOOK_OUTPUT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How often the child is checked for having exited
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The commands a worker thread runs for one start of a service
+pub struct Launch {
+    pub command: Command,
+    pub pre_start: Vec<Hook>,
+    pub post_start: Vec<Hook>,
+    pub pre_stop: Vec<Hook>,
+    pub post_stop: Vec<Hook>,
+    /// Whether exiting with 0 means that the service completed
+    pub oneshot: bool,
+    /// The cgroup the command runs in, which contains everything it started
+    pub cgroup: Option<PathBuf>,
 }
 
+pub struct Hook {
+    /// The command as it is written in the config, it is shown in the output
+    pub command: String,
+    pub process: Command,
+}
+
+/// Runs the hooks and the command of a service and reports how it ended in `service_status`.
+/// The `post_stop` hooks are run however it ended, but when the service manager quits, it only
+/// waits a limited time for them.
 pub fn child_process_thread(
-    mut child: Child,
-    mut stdout_send: mpsc::Sender<StdioSendBuf>,
+    mut launch: Launch,
+    stdout_send: mpsc::Sender<StdioSendBuf>,
     service_status: Arc<Mutex<ServiceStatus>>,
     service_name: String,
     terminate_channel: mpsc::Receiver<()>,
 ) -> SmResult {
+    let result = run_service(
+        &mut launch,
+        &stdout_send,
+        &service_status,
+        &service_name,
+        &terminate_channel,
+    );
+
+    let hooks = run_hooks(
+        "post_stop",
+        &mut launch.post_stop,
+        &stdout_send,
+        &terminate_channel,
+    );
+
+    // the status is only set now so that a restart waits for the hooks. It is set even if
+    // something went wrong, a service that stays active can't be started again.
+    *service_status.lock()? = match &result {
+        Ok(status) => *status,
+        Err(_) => ServiceStatus::Killed,
+    };
+
+    result?;
+    hooks?;
+
+    Ok(())
+}
+
+/// Runs the service until it exits or is asked to stop, returning the status it ended with
+fn run_service(
+    launch: &mut Launch,
+    stdout_send: &mpsc::Sender<StdioSendBuf>,
+    service_status: &Mutex<ServiceStatus>,
+    service_name: &str,
+    terminate_channel: &mpsc::Receiver<()>,
+) -> Result<ServiceStatus, SmError> {
+    let status = run_hooks(
+        "pre_start",
+        &mut launch.pre_start,
+        stdout_send,
+        terminate_channel,
+    )?;
+    if status != ServiceStatus::Exited {
+        return Ok(status);
+    }
+
+    let mut child = match launch.command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            error!(error = %err, "Failed to start child process");
+            send_message(stdout_send, &format!("{}\n", err))?;
+            return Ok(ServiceStatus::FailedToStart);
+        }
+    };
+
+    // services that depend on this one can start now
+    *service_status.lock()? = ServiceStatus::Running;
+
     let stdout = child
         .stdout
         .take()
@@ -29,134 +117,271 @@ pub fn child_process_thread(
         .take()
         .ok_or(SmError::Bug("Stderr of child could not be taken"))?;
 
-    let (stderr_terminate_send, stderr_terminate_recv) = mpsc::channel();
+    spawn_output_thread(
+        format!("worker-stdout-({})", service_name),
+        stdout,
+        stdout_send,
+    );
+    spawn_output_thread(
+        format!("worker-stderr-({})", service_name),
+        stderr,
+        stdout_send,
+    );
+
+    // a failing post_start hook doesn't stop the service, but a stop request during it does
+    let status = run_hooks(
+        "post_start",
+        &mut launch.post_start,
+        stdout_send,
+        terminate_channel,
+    )?;
+    let mut terminated = status == ServiceStatus::Killed;
+
+    info!(name = %service_name, "Entering main processing loop");
 
-    let stdout_send_2 = stdout_send.clone();
+    loop {
+        terminated |= is_terminated(terminate_channel);
+        if terminated {
+            run_hooks(
+                "pre_stop",
+                &mut launch.pre_stop,
+                stdout_send,
+                terminate_channel,
+            )?;
+            let cgroup = launch.cgroup.as_deref();
+            return stop_child(&mut child, cgroup, stdout_send, service_status);
+        }
 
-    let stderr_thread_result = thread::Builder::new()
-        .name(format!("worker-stderr-({})", service_name))
-        .spawn(move || child_process_stderr_thread(stdout_send_2, stderr_terminate_recv, stderr));
+        if let Some(status) = child.try_wait()? {
+            return Ok(match status.code() {
+                Some(0) if launch.oneshot => ServiceStatus::Completed,
+                _ => exit_status(status),
+            });
+        }
 
-    if let Err(err) = stderr_thread_result {
-        error!(error = %err, "Failed to spawn stderr thread");
+        thread::sleep(POLL_INTERVAL);
     }
+}
 
-    info!(name = %service_name, "Entering main processing loop");
-    let (action, result) = child_process_main_thread(
-        &mut stdout_send,
-        terminate_channel,
-        stderr_terminate_send,
-        stdout,
-        &mut child,
-        service_status.clone(),
-    );
+/// Runs the hooks one after another with their output going to the log of the service. Stops
+/// at the first hook that fails and returns how it ended, `Exited` if all of them succeeded. A
+/// stop request stops the running hook like a service and returns `Killed`.
+fn run_hooks(
+    name: &str,
+    hooks: &mut [Hook],
+    stdout_send: &mpsc::Sender<StdioSendBuf>,
+    terminate_channel: &mpsc::Receiver<()>,
+) -> Result<ServiceStatus, SmError> {
+    for hook in hooks {
+        send_message(
+            stdout_send,
+            &format!("--- {}: {} ---\n", name, hook.command),
+        )?;
+
+        let status = match hook.process.spawn() {
+            Ok(mut child) => {
+                let mut output_threads = Vec::new();
+                if let Some(stdout) = child.stdout.take() {
+                    let name = format!("hook-stdout-({})", name);
+                    output_threads.extend(spawn_output_thread(name, stdout, stdout_send));
+                }
+                if let Some(stderr) = child.stderr.take() {
+                    let name = format!("hook-stderr-({})", name);
+                    output_threads.extend(spawn_output_thread(name, stderr, stdout_send));
+                }
+
+                let status = loop {
+                    if is_terminated(terminate_channel) {
+                        terminate(&mut child, None)?;
+                        break None;
+                    }
+
+                    if let Some(status) = child.try_wait()? {
+                        break Some(status);
+                    }
+
+                    thread::sleep(POLL_INTERVAL);
+                };
 
-    if let ChildAction::Kill = action {
-        match child.kill() {
-            Ok(()) => {
-                *service_status.lock().map_err(|_| SmError::MutexPoisoned)? = ServiceStatus::Killed
+                // the output has to be sent before whatever comes after the hook
+                let deadline = Instant::now() + HOOK_OUTPUT_TIMEOUT;
+                while Instant::now() < deadline
+                    && !output_threads.iter().all(JoinHandle::is_finished)
+                {
+                    thread::sleep(POLL_INTERVAL);
+                }
+
+                status.map_or(ServiceStatus::Killed, exit_status)
             }
-            Err(e) if e.kind() == io::ErrorKind::InvalidInput => {}
-            Err(e) => return Err(e.into()),
-        }
+            Err(err) => {
+                send_message(stdout_send, &format!("{}\n", err))?;
+                ServiceStatus::FailedToStart
+            }
+        };
 
-        let mut send_message_buf = [0; STDIO_SEND_BUF_SIZE];
-        let kill_msg = "\n\n<Process was killed>\n";
-        send_message_buf
-            .as_mut_slice()
-            .write_all(kill_msg.as_bytes())?;
-        stdout_send
-            .send((send_message_buf, kill_msg.len()))
-            .map_err(|_| SmError::Bug("Failed to send stdout to main thread"))?;
+        if status != ServiceStatus::Exited {
+            send_message(stdout_send, &format!("--- {} {} ---\n", name, status))?;
+            return Ok(status);
+        }
     }
 
-    result
+    Ok(ServiceStatus::Exited)
 }
 
-fn child_process_main_thread(
-    stdout_send: &mut mpsc::Sender<StdioSendBuf>,
-    terminate_channel: mpsc::Receiver<()>,
-    stderr_terminate_send: mpsc::Sender<()>,
-    mut stdout: ChildStdout,
+fn exit_status(status: ExitStatus) -> ServiceStatus {
+    match status.code() {
+        Some(0) => ServiceStatus::Exited,
+        Some(code) => ServiceStatus::Failed(code),
+        None => ServiceStatus::Killed,
+    }
+}
+
+/// Asks the child to stop and reports that it was killed. Processes that are left in the cgroup
+/// of the service afterwards are killed as well.
+fn stop_child(
     child: &mut Child,
-    service_status: Arc<Mutex<ServiceStatus>>,
-) -> (ChildAction, SmResult) {
-    loop {
-        match terminate_channel.try_recv() {
-            Ok(_) | Err(TryRecvError::Disconnected) => {
-                // terminating the thread is a best-effort, it doesn't matter if it died
-                let _ = stderr_terminate_send.send(());
-                return (ChildAction::Kill, Ok(()));
+    cgroup: Option<&Path>,
+    stdout_send: &mpsc::Sender<StdioSendBuf>,
+    service_status: &Mutex<ServiceStatus>,
+) -> Result<ServiceStatus, SmError> {
+    *service_status.lock()? = ServiceStatus::Stopping;
+
+    terminate(child, cgroup)?;
+
+    // processes that outlived the child aren't left running
+    kill_cgroup(cgroup);
+
+    // the message is sent first so that it still belongs to this run if the service is restarted
+    send_message(stdout_send, "\n\n<Process was killed>\n")?;
+
+    Ok(ServiceStatus::Killed)
+}
+
+/// Asks the child to stop with `SIGTERM` and kills it if it is still running after
+/// [`STOP_TIMEOUT`]. The signals are sent to the whole process group of the child, so that
+/// processes started by the shell are stopped as well. If a cgroup is given, it is killed instead,
+/// together with the processes that left the process group.
+fn terminate(child: &mut Child, cgroup: Option<&Path>) -> SmResult {
+    signal_process_group(child, libc::SIGTERM)?;
+
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    let mut killed = false;
+
+    while child.try_wait()?.is_none() {
+        if !killed && Instant::now() >= deadline {
+            info!("Child did not stop in time, killing it");
+            if !kill_cgroup(cgroup) {
+                signal_process_group(child, libc::SIGKILL)?;
             }
-            Err(TryRecvError::Empty) => {}
+            killed = true;
         }
 
-        let mut stdout_buf = [0; STDIO_SEND_BUF_SIZE];
-        match stdout.read(&mut stdout_buf) {
-            Ok(0) => {}
-            Ok(n) => match stdout_send.send((stdout_buf, n)) {
-                Ok(()) => {}
-                Err(_) => {
-                    return (
-                        ChildAction::Kill,
-                        Err(SmError::Bug("Failed to send stdout to main thread")),
-                    )
-                }
-            },
-            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
-            // todo: maybe we are overreacting here?
-            Err(err) => return (ChildAction::Kill, Err(err.into())),
-        };
+        thread::sleep(POLL_INTERVAL);
+    }
 
-        match child.try_wait() {
-            Ok(None) => {}
-            Ok(Some(status)) => {
-                let mut status_lock = match service_status.lock() {
-                    Ok(lock) => lock,
-                    Err(_) => return (ChildAction::Kill, Err(SmError::MutexPoisoned)),
-                };
+    Ok(())
+}
 
-                *status_lock = match status.code() {
-                    Some(0) => ServiceStatus::Exited,
-                    Some(code) => ServiceStatus::Failed(code),
-                    None => ServiceStatus::Killed,
-                };
+/// Whether the worker was asked to stop the service, which is also the case if the service
+/// manager dropped the channel
+fn is_terminated(terminate_channel: &mpsc::Receiver<()>) -> bool {
+    match terminate_channel.try_recv() {
+        Ok(_) | Err(TryRecvError::Disconnected) => true,
+        Err(TryRecvError::Empty) => false,
+    }
+}
+
+/// Kills every process in the cgroup, returns whether that worked
+fn kill_cgroup(cgroup: Option<&Path>) -> bool {
+    let cgroup = match cgroup {
+        Some(cgroup) => cgroup,
+        None => return false,
+    };
+
+    match cgroup::kill(cgroup) {
+        Ok(killed) => killed,
+        Err(err) => {
+            error!(error = %err, cgroup = %cgroup.display(), "Failed to kill cgroup");
+            false
+        }
+    }
+}
+
+fn signal_process_group(child: &Child, signal: libc::c_int) -> SmResult {
+    let pid = libc::pid_t::try_from(child.id())
+        .map_err(|_| SmError::Bug("Child process id does not fit into pid_t"))?;
+
+    // SAFETY: `kill` has no memory safety requirements. The child was spawned as the leader of
+    // its own process group and hasn't been waited on yet, so the group still belongs to it.
+    let result = unsafe { libc::kill(-pid, signal) };
 
-                return (ChildAction::Ignore, Ok(()));
+    match result {
+        0 => Ok(()),
+        _ => {
+            let err = io::Error::last_os_error();
+            // the whole group has already exited
+            if err.raw_os_error() == Some(libc::ESRCH) {
+                Ok(())
+            } else {
+                Err(err.into())
             }
-            Err(err) => return (ChildAction::Kill, Err(err.into())),
         }
     }
 }
 
-fn child_process_stderr_thread(
-    stdout_send: mpsc::Sender<StdioSendBuf>,
-    terminate_channel: mpsc::Receiver<()>,
-    mut stderr: ChildStderr,
-) {
-    info!("Entering main processing loop for stderr thread");
+pub fn send_message(stdout_send: &mpsc::Sender<StdioSendBuf>, msg: &str) -> SmResult {
+    for chunk in msg.as_bytes().chunks(STDIO_SEND_BUF_SIZE) {
+        let mut send_message_buf = [0; STDIO_SEND_BUF_SIZE];
+        send_message_buf[..chunk.len()].copy_from_slice(chunk);
+        stdout_send
+            .send((send_message_buf, chunk.len(), SystemTime::now()))
+            .map_err(|_| SmError::FailedToSendStdio)?;
+    }
 
-    loop {
-        match terminate_channel.try_recv() {
-            Ok(_) | Err(TryRecvError::Disconnected) => return,
-            Err(TryRecvError::Empty) => {}
+    Ok(())
+}
+
+fn spawn_output_thread<R: Read + Send + 'static>(
+    name: String,
+    output: R,
+    stdout_send: &mpsc::Sender<StdioSendBuf>,
+) -> Option<JoinHandle<()>> {
+    let stdout_send = stdout_send.clone();
+
+    let thread_result = thread::Builder::new()
+        .name(name)
+        .spawn(move || child_process_output_thread(stdout_send, output));
+
+    match thread_result {
+        Ok(handle) => Some(handle),
+        Err(err) => {
+            error!(error = %err, "Failed to spawn output thread");
+            None
         }
+    }
+}
 
-        let mut stderr_buf = [0; STDIO_SEND_BUF_SIZE];
-        match stderr.read(&mut stderr_buf) {
-            Ok(0) => {}
+/// Forwards stdout or stderr of the child to the main thread until it is closed
+fn child_process_output_thread<R: Read>(stdout_send: mpsc::Sender<StdioSendBuf>, mut output: R) {
+    info!("Entering main processing loop for output thread");
+
+    loop {
+        let mut output_buf = [0; STDIO_SEND_BUF_SIZE];
+        match output.read(&mut output_buf) {
+            Ok(0) => return,
             Ok(n) => {
                 let result = stdout_send
-                    .send((stderr_buf, n))
-                    .map_err(|_| SmError::Bug("Failed to send stderr to main thread"));
+                    .send((output_buf, n, SystemTime::now()))
+                    .map_err(|_| SmError::Bug("Failed to send output to main thread"));
 
                 if let Err(err) = result {
                     error!(error = %err);
+                    return;
                 }
             }
             Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
             Err(err) => {
-                error!(error = %err, "Error reading from stderr");
+                error!(error = %err, "Error reading output of child");
                 return;
             }
         };
@@ -0,0 +1,212 @@
+use crate::model::config::InheritEnv;
+use crate::model::Service;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::{env, fs, io};
+
+/// The environment a service is started with. Inherited variables are overridden by the env
+/// files in order, which are overridden by the `env` of the service.
+pub fn resolve_env(service: &Service) -> io::Result<BTreeMap<String, String>> {
+    let mut vars = inherited_env(&service.inherit_env);
+
+    vars.extend(read_env_files(&service.env_files)?);
+
+    vars.extend(
+        service
+            .env
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone())),
+    );
+
+    Ok(vars)
+}
+
+/// The variables of the environment of the service manager that are passed on to a service
+pub fn inherited_env(inherit_env: &InheritEnv) -> BTreeMap<String, String> {
+    match inherit_env {
+        InheritEnv::All(true) => env::vars().collect(),
+        InheritEnv::All(false) => BTreeMap::new(),
+        InheritEnv::Only(names) => env::vars()
+            .filter(|(name, _)| names.contains(name))
+            .collect(),
+    }
+}
+
+/// The variables of the env files, where later files override earlier ones
+pub fn read_env_files(paths: &[PathBuf]) -> io::Result<BTreeMap<String, String>> {
+    let mut vars = BTreeMap::new();
+
+    for path in paths {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            // env files are optional, so that local overrides like `.env.local` don't have to exist
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!("{}: {}", path.display(), err),
+                ))
+            }
+        };
+
+        let file_vars = parse_dotenv(&content).map_err(|(line, msg)| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path.display(), line, msg),
+            )
+        })?;
+
+        vars.extend(file_vars);
+    }
+
+    Ok(vars)
+}
+
+/// Parses the content of a `.env` file. Lines look like `KEY=value` and may start with `export`.
+/// Values can be single quoted to be taken literally or double quoted to allow escapes like `\n`
+/// and to span several lines. Unquoted values end at a ` #` comment.
+///
+/// Errors contain the line they occurred on.
+pub fn parse_dotenv(content: &str) -> Result<Vec<(String, String)>, (usize, String)> {
+    let mut vars = Vec::new();
+    let mut lines = content.lines().enumerate();
+
+    while let Some((index, line)) = lines.next() {
+        let line_number = index + 1;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| (line_number, "expected `KEY=value`".to_string()))?;
+
+        let name = name.trim();
+        let is_valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+
+        if !is_valid_name {
+            return Err((line_number, format!("invalid variable name `{}`", name)));
+        }
+
+        let value = value.trim_start();
+
+        let value = if let Some(rest) = value.strip_prefix('\'') {
+            let (value, _) = rest
+                .split_once('\'')
+                .ok_or_else(|| (line_number, "unterminated single quote".to_string()))?;
+            value.to_string()
+        } else if let Some(rest) = value.strip_prefix('"') {
+            parse_double_quoted(rest, &mut lines.by_ref().map(|(_, line)| line))
+                .ok_or_else(|| (line_number, "unterminated double quote".to_string()))?
+        } else {
+            let value = match value.find(" #") {
+                Some(comment) => &value[..comment],
+                None => value,
+            };
+            value.trim_end().to_string()
+        };
+
+        vars.push((name.to_string(), value));
+    }
+
+    Ok(vars)
+}
+
+/// Parses a double quoted value after the opening quote. If the closing quote isn't on the same
+/// line, the following lines are consumed until it is found.
+fn parse_double_quoted<'a>(
+    first_line: &str,
+    next_lines: &mut impl Iterator<Item = &'a str>,
+) -> Option<String> {
+    let mut value = String::new();
+    let mut line = first_line.to_string();
+
+    loop {
+        let mut chars = line.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Some(value),
+                '\\' => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some(other) => value.push(other),
+                    None => value.push('\\'),
+                },
+                c => value.push(c),
+            }
+        }
+
+        value.push('\n');
+        line = next_lines.next()?.to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_dotenv;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn plain_values() {
+        let parsed = parse_dotenv("A=1\nB = two words \nEMPTY=\n").unwrap();
+        assert_eq!(
+            parsed,
+            vars(&[("A", "1"), ("B", "two words"), ("EMPTY", "")])
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let content = "# a comment\n\n   \nA=1 # trailing comment\n  # indented comment\nB=a#b\n";
+        let parsed = parse_dotenv(content).unwrap();
+        assert_eq!(parsed, vars(&[("A", "1"), ("B", "a#b")]));
+    }
+
+    #[test]
+    fn export_prefix() {
+        let parsed = parse_dotenv("export A=1\nexport   B=2\nexported=3\n").unwrap();
+        assert_eq!(parsed, vars(&[("A", "1"), ("B", "2"), ("exported", "3")]));
+    }
+
+    #[test]
+    fn single_quotes_are_literal() {
+        let parsed = parse_dotenv(r"A='a \n $B # not a comment'").unwrap();
+        assert_eq!(parsed, vars(&[("A", r"a \n $B # not a comment")]));
+    }
+
+    #[test]
+    fn double_quotes_have_escapes() {
+        let parsed = parse_dotenv(r#"A="tab\there \"quoted\" \\ # kept""#).unwrap();
+        assert_eq!(parsed, vars(&[("A", "tab\there \"quoted\" \\ # kept")]));
+    }
+
+    #[test]
+    fn double_quotes_span_lines() {
+        let parsed = parse_dotenv("A=\"first\nsecond\"\nB=1\n").unwrap();
+        assert_eq!(parsed, vars(&[("A", "first\nsecond"), ("B", "1")]));
+    }
+
+    #[test]
+    fn errors_have_the_line() {
+        assert_eq!(parse_dotenv("A=1\nnot a var\n").unwrap_err().0, 2);
+        assert_eq!(parse_dotenv("\nA-B=1").unwrap_err().0, 2);
+        assert_eq!(parse_dotenv("=1").unwrap_err().0, 1);
+        assert_eq!(parse_dotenv("A='open").unwrap_err().0, 1);
+        assert_eq!(parse_dotenv("A=1\nB=\"open\nstill open").unwrap_err().0, 2);
+    }
+}
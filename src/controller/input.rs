@@ -0,0 +1,66 @@
+use crate::model::keymap::{Action, KeyBinding, Keymap, Lookup};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Collects key presses until they form a key sequence from the keymap, optionally preceded by a
+/// count like the `5` in `5j`.
+#[derive(Debug, Default)]
+pub struct KeyInput {
+    count: Option<usize>,
+    pending: Vec<KeyBinding>,
+}
+
+impl KeyInput {
+    /// Feeds a key press into the state machine, returning the action and how often it should be
+    /// repeated once a complete sequence was entered.
+    pub fn feed(&mut self, key: KeyEvent, keymap: &Keymap) -> Option<(Action, usize)> {
+        let binding = KeyBinding::from(key);
+
+        if self.pending.is_empty() && keymap.lookup(&[binding]) == Lookup::None {
+            if let Some(digit) = count_digit(binding, self.count.is_some()) {
+                let count = self.count.unwrap_or(0);
+                self.count = Some(count.saturating_mul(10).saturating_add(digit));
+                return None;
+            }
+        }
+
+        self.pending.push(binding);
+
+        match keymap.lookup(&self.pending) {
+            Lookup::Action(action) => Some(self.finish(action)),
+            Lookup::Prefix => None,
+            Lookup::None => {
+                // the sequence was broken off, but the last key could still start a new one
+                let retry = self.pending.len() > 1;
+                self.reset();
+
+                if retry {
+                    self.feed(key, keymap)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, action: Action) -> (Action, usize) {
+        let count = self.count.unwrap_or(1).max(1);
+        self.reset();
+        (action, count)
+    }
+
+    fn reset(&mut self) {
+        self.count = None;
+        self.pending.clear();
+    }
+}
+
+/// A digit that is part of a count. `0` only continues a count, like in vim.
+fn count_digit(binding: KeyBinding, has_count: bool) -> Option<usize> {
+    match binding.code {
+        KeyCode::Char(c) if binding.modifiers == KeyModifiers::empty() => c
+            .to_digit(10)
+            .filter(|digit| *digit != 0 || has_count)
+            .map(|digit| digit as usize),
+        _ => None,
+    }
+}
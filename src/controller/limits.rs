@@ -0,0 +1,123 @@
+use crate::model::config::Limits;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// Makes the command set the resource limits, the niceness and the OOM score adjustment of its
+/// process before it is executed. A limit that can't be set makes the start fail.
+pub fn apply_limits(
+    cmd: &mut Command,
+    limits: &Limits,
+    nice: Option<i32>,
+    oom_score_adj: Option<i32>,
+) {
+    // everything that allocates has to be done before the fork
+    let rlimits = limits
+        .iter()
+        .filter_map(|(name, limit)| {
+            let resource = resource(name)?;
+            // the loader made sure that the limits are valid
+            let value = limit.value().ok()?.unwrap_or(libc::RLIM_INFINITY);
+
+            Some((resource, value as libc::rlim_t))
+        })
+        .collect::<Vec<_>>();
+    let oom_score_adj = oom_score_adj.map(|adj| adj.to_string());
+
+    if rlimits.is_empty() && nice.is_none() && oom_score_adj.is_none() {
+        return;
+    }
+
+    // SAFETY: the closure runs in the forked child, where only async-signal-safe functions may
+    // be called. It only calls `setrlimit`, `setpriority`, `open`, `write` and `close` and
+    // doesn't allocate.
+    unsafe {
+        cmd.pre_exec(move || {
+            for (resource, value) in &rlimits {
+                let limit = libc::rlimit {
+                    rlim_cur: *value,
+                    rlim_max: *value,
+                };
+                if libc::setrlimit(*resource, &limit) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if let Some(oom_score_adj) = &oom_score_adj {
+                write_oom_score_adj(oom_score_adj)?;
+            }
+
+            Ok(())
+        });
+    }
+}
+
+/// The hard limit of the service manager that a service can't go above, `None` if it is unlimited
+/// or the service manager runs as root, which can raise it
+pub fn hard_limit(name: &str) -> Option<u64> {
+    let resource = resource(name)?;
+
+    // SAFETY: `geteuid` can't fail and `getrlimit` only writes to the given struct
+    unsafe {
+        if libc::geteuid() == 0 {
+            return None;
+        }
+
+        let mut limit = std::mem::zeroed::<libc::rlimit>();
+        if libc::getrlimit(resource, &mut limit) != 0 || limit.rlim_max == libc::RLIM_INFINITY {
+            return None;
+        }
+
+        Some(limit.rlim_max as u64)
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+/// The resource of a limit by its name in the config
+fn resource(name: &str) -> Option<Resource> {
+    let resource = match name {
+        "nofile" => libc::RLIMIT_NOFILE,
+        "nproc" => libc::RLIMIT_NPROC,
+        "as" => libc::RLIMIT_AS,
+        "data" => libc::RLIMIT_DATA,
+        "stack" => libc::RLIMIT_STACK,
+        "fsize" => libc::RLIMIT_FSIZE,
+        "core" => libc::RLIMIT_CORE,
+        "memlock" => libc::RLIMIT_MEMLOCK,
+        "cpu_seconds" => libc::RLIMIT_CPU,
+        _ => return None,
+    };
+    Some(resource)
+}
+
+/// Writes to `/proc/self/oom_score_adj` without allocating
+///
+/// # Safety
+///
+/// Only async-signal-safe functions are called, so it can be called in a forked child.
+unsafe fn write_oom_score_adj(value: &str) -> io::Result<()> {
+    let fd = libc::open(c"/proc/self/oom_score_adj".as_ptr(), libc::O_WRONLY);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let written = libc::write(fd, value.as_ptr().cast(), value.len());
+    let result = match written {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    };
+
+    libc::close(fd);
+
+    result
+}
@@ -1,51 +1,91 @@
+mod cgroup;
 mod child;
+mod env;
+mod input;
+mod limits;
 
-use crate::controller::child::child_process_thread;
-use crate::model::config::Config;
-use crate::model::{AppState, Service, ServiceStatus, SmError, SmResult, StdIoStream};
+pub use crate::controller::cgroup::Cgroups;
+use crate::controller::child::{child_process_thread, send_message, Hook, Launch};
+pub use crate::controller::env::{inherited_env, read_env_files};
+use crate::controller::input::KeyInput;
+use crate::controller::limits::apply_limits;
+pub use crate::controller::limits::hard_limit;
+use crate::model::config::{self, CommandLine, Config, InheritEnv, ServiceKind};
+use crate::model::keymap::{Action, Keymap};
+use crate::model::schedule::Schedule;
+use crate::model::{
+    AppState, Details, Hitboxes, Hooks, MergedView, Output, OutputLine, Pane, Run, Service,
+    ServiceStatus, SmError, SmResult, SplitLayout, SplitView, StdIoStream, Tab, TableRow,
+};
 use crate::{view, App};
 use crossterm::event;
-use crossterm::event::{Event, KeyCode};
-use std::collections::HashMap;
-use std::io::{ErrorKind, Write};
+use crossterm::event::{Event, MouseButton, MouseEvent, MouseEventKind};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::ErrorKind;
+use std::ops::ControlFlow;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use std::sync::{mpsc, Arc, Mutex};
-use std::time::Duration;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
 use std::{io, thread};
 use tracing::{error, info};
 use tui::backend::Backend;
+use tui::layout::Rect;
 use tui::widgets::TableState;
 use tui::Terminal;
 
 const STDIO_SEND_BUF_SIZE: usize = 512;
 
-pub type StdioSendBuf = ([u8; STDIO_SEND_BUF_SIZE], usize);
+/// Two clicks on the same row within this time count as a double click
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+
+/// How many lines a log is scrolled per step of the mouse wheel
+const WHEEL_SCROLL_LINES: usize = 3;
+
+/// How many lines of output are kept per run before the oldest ones are dropped
+const MAX_OUTPUT_LINES: usize = 10_000;
+
+/// How many runs of every service are kept
+const MAX_RUNS: usize = 10;
+
+/// How long the services are waited for to stop when the service manager quits. It is a bit
+/// longer than the time a service has to stop before it is killed.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(7);
+
+pub type StdioSendBuf = ([u8; STDIO_SEND_BUF_SIZE], usize, SystemTime);
 
 pub fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> SmResult {
     info!("Entering main loop");
 
+    let mut input = KeyInput::default();
+    let mut last_click = None;
+
     loop {
         terminal.draw(|f| view::render_ui(f, &mut app))?;
 
         app.recv_stdouts();
+        app.record_exits()?;
+        app.start_pending_restarts()?;
+        app.start_waiting_services()?;
+        app.start_scheduled_services()?;
+        app.update_usage();
 
         if event::poll(Duration::from_millis(10))? {
-            if let Event::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::Char('q') => match app.selected {
-                        Some(_) => app.leave_service(),
-                        None => {
-                            break;
-                        }
-                    },
-                    KeyCode::Char('r') => app.run_service()?,
-                    KeyCode::Char('k') => app.kill_service()?,
-                    KeyCode::Down => app.next(),
-                    KeyCode::Up => app.previous(),
-                    KeyCode::Enter => app.select_service(),
-                    KeyCode::Esc => app.leave_service(),
-                    _ => {}
-                }
+            let half_page = (terminal.size()?.height / 2) as usize;
+
+            let flow = match event::read()? {
+                Event::Key(key) => match input.feed(key, &app.keymap) {
+                    Some((action, count)) => app.handle_action(action, count, half_page)?,
+                    None => ControlFlow::Continue(()),
+                },
+                Event::Mouse(mouse) => app.handle_mouse(mouse, &mut last_click, half_page)?,
+                Event::Resize(..) => ControlFlow::Continue(()),
+            };
+
+            if flow.is_break() {
+                break;
             }
         }
     }
@@ -57,109 +97,535 @@ pub fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> SmResult
         let _ = sender.send(());
     }
 
+    // the children are in their own process groups, so they would keep running if the worker
+    // threads didn't get to stop them
+    let deadline = Instant::now() + QUIT_TIMEOUT;
+    while !app.workers.values().all(JoinHandle::is_finished) {
+        if Instant::now() >= deadline {
+            info!("Services did not stop in time");
+            break;
+        }
+
+        terminal.draw(|f| view::render_ui(f, &mut app))?;
+        app.recv_stdouts();
+        app.record_exits()?;
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // the worker threads end with the service manager, so the cgroups make sure that nothing keeps
+    // running
+    if let Some(cgroups) = &app.cgroups {
+        cgroups.remove_all();
+    }
+
     Ok(())
 }
 
 impl App {
-    pub fn new(config: Config) -> io::Result<App> {
-        Ok(App {
+    pub fn new(config: Config, keymap: Keymap) -> io::Result<App> {
+        let global_env_files = config.env_file;
+        let global_inherit_env = config.inherit_env.unwrap_or_default();
+        let global_shell = config.shell;
+
+        let services = config
+            .services
+            .into_iter()
+            .map(|(name, service)| (name, service, false));
+        let tasks = config
+            .tasks
+            .into_iter()
+            .map(|(name, service)| (name, service, true));
+
+        let mut app = App {
             table: AppState {
                 table_state: TableState::default(),
-                services: config
-                    .into_iter()
-                    .map(|(name, service)| -> io::Result<Service> {
-                        let (stdout_send, stdout_recv) = mpsc::channel();
-
-                        Ok(Service {
-                            command: service.command,
+                table_offset: 0,
+                services: services
+                    .chain(tasks)
+                    .map(|(name, service, task)| {
+                        new_service(
                             name,
-                            workdir: service
-                                .workdir
-                                .ok_or_else(|| io::Error::from(ErrorKind::Other))
-                                .or_else(|_| std::env::current_dir())?,
-                            env: service.env.unwrap_or_else(HashMap::new),
-                            status: Arc::new(Mutex::new(ServiceStatus::NotStarted)),
-                            std_io_buf: Vec::new(),
-                            stdout: StdIoStream {
-                                recv: stdout_recv,
-                                send: stdout_send,
-                            },
-                        })
+                            service,
+                            task,
+                            &global_env_files,
+                            &global_inherit_env,
+                            global_shell.as_deref(),
+                        )
                     })
                     .collect::<io::Result<_>>()?,
+                collapsed: HashSet::new(),
+                tab: Tab::Services,
             },
             selected: None,
+            scroll: 0,
+            viewed_run: None,
+            details: None,
+            split: None,
+            merged: None,
             thread_terminates: HashMap::new(),
-        })
+            workers: HashMap::new(),
+            pending_restarts: HashSet::new(),
+            keymap,
+            hitboxes: Hitboxes::default(),
+            cgroups: Cgroups::init(),
+        };
+
+        // services of the same group have to be next to each other to be shown below their header
+        app.table
+            .services
+            .sort_by(|a, b| (a.task, &a.group, &a.name).cmp(&(b.task, &b.group, &b.name)));
+
+        Ok(app)
+    }
+
+    fn handle_action(
+        &mut self,
+        action: Action,
+        count: usize,
+        half_page: usize,
+    ) -> Result<ControlFlow<()>, SmError> {
+        match action {
+            Action::Quit => {
+                if self.is_table() {
+                    return Ok(ControlFlow::Break(()));
+                }
+                self.leave_service();
+            }
+            Action::Run => self.run_services(self.current_services())?,
+            Action::Kill => self.kill_services(self.current_services())?,
+            Action::Restart => self.restart_services(self.current_services())?,
+            Action::RunAll => self.run_services(self.all_services())?,
+            Action::KillAll => self.kill_services(self.all_services())?,
+            Action::Mark => self.toggle_mark(),
+            Action::Split => self.open_split(),
+            Action::ToggleLayout => self.toggle_split_layout(),
+            Action::Merged => self.open_merged(),
+            Action::ToggleFilter => self.toggle_merged_filter(),
+            Action::Tasks => self.switch_tab(),
+            Action::NextPane => self.focus_next_pane(),
+            Action::Left => self.merged_cursor_left(),
+            Action::Right => self.merged_cursor_right(),
+            Action::Down => self.next(count),
+            Action::Up => self.previous(count),
+            Action::HalfPageDown => self.page_down(half_page.saturating_mul(count)),
+            Action::HalfPageUp => self.page_up(half_page.saturating_mul(count)),
+            Action::Top => self.top(),
+            Action::Bottom => self.bottom(),
+            Action::Details => self.toggle_details(),
+            Action::PreviousRun => self.previous_run(),
+            Action::NextRun => self.next_run(),
+            Action::Select => self.select_service(),
+            Action::Back => self.leave_service(),
+        }
+
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Handles clicks and the mouse wheel, using the areas of the last drawn frame to find out
+    /// what was clicked
+    fn handle_mouse(
+        &mut self,
+        mouse: MouseEvent,
+        last_click: &mut Option<(Instant, usize)>,
+        half_page: usize,
+    ) -> Result<ControlFlow<()>, SmError> {
+        let (column, row) = (mouse.column, mouse.row);
+
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let hint = self.hitboxes.hint_at(column, row);
+                if let Some(action) = hint {
+                    return self.handle_action(action, 1, half_page);
+                }
+
+                if let Some(index) = self.hitboxes.row_at(column, row) {
+                    self.table.table_state.select(Some(index));
+
+                    let now = Instant::now();
+                    let is_double_click = matches!(
+                        last_click,
+                        Some((time, clicked)) if *clicked == index
+                            && now.duration_since(*time) < DOUBLE_CLICK_TIME
+                    );
+
+                    if is_double_click {
+                        *last_click = None;
+                        self.select_service();
+                    } else {
+                        *last_click = Some((now, index));
+                    }
+                }
+
+                if let Some(pane) = self.hitboxes.pane_at(column, row) {
+                    if let Some(split) = &mut self.split {
+                        split.focused = pane;
+                    }
+                }
+            }
+            MouseEventKind::ScrollDown => match self.log_scroll() {
+                Some(_) => self.page_down(WHEEL_SCROLL_LINES),
+                None => self.page_down(1),
+            },
+            MouseEventKind::ScrollUp => match self.log_scroll() {
+                Some(_) => self.page_up(WHEEL_SCROLL_LINES),
+                None => self.page_up(1),
+            },
+            _ => {}
+        }
+
+        Ok(ControlFlow::Continue(()))
     }
 
     pub fn is_table(&self) -> bool {
-        self.selected.is_none()
+        self.selected.is_none() && self.split.is_none() && self.merged.is_none()
+    }
+
+    /// The services that actions like run and kill apply to in the current view. If a group is
+    /// selected in the table, these are all services of the group.
+    fn current_services(&self) -> Vec<usize> {
+        if let Some(split) = &self.split {
+            return split
+                .panes
+                .get(split.focused)
+                .map(|pane| pane.service)
+                .into_iter()
+                .collect();
+        }
+
+        if let Some(selected) = self.selected {
+            return vec![selected];
+        }
+
+        match self.table.selected_row() {
+            Some(TableRow::Service(index)) => vec![index],
+            Some(TableRow::Group(group)) => self.table.group_services(&group),
+            None => Vec::new(),
+        }
+    }
+
+    /// The services of the current tab
+    fn all_services(&self) -> Vec<usize> {
+        self.table
+            .services
+            .iter()
+            .enumerate()
+            .filter(|(_, service)| self.table.is_in_tab(service))
+            .map(|(index, _)| index)
+            .collect()
     }
 
     fn recv_stdouts(&mut self) {
         for service in self.table.services.iter_mut() {
-            while let Ok((buf, n)) = service.stdout.recv.try_recv() {
-                service.std_io_buf.extend(&buf[0..n]);
+            while let Ok((buf, n, time)) = service.stdout.recv.try_recv() {
+                if let Some(run) = service.runs.back_mut() {
+                    run.output.push(&buf[0..n], time);
+                }
+            }
+        }
+    }
+
+    /// Stores how the current run of every service ended once its worker thread reported it
+    fn record_exits(&mut self) -> SmResult {
+        for service in self.table.services.iter_mut() {
+            let status = *service.status.lock()?;
 
-                if service.std_io_buf.len() > 2_000_000 {
-                    service.std_io_buf.clear(); // todo don't
+            if let Some(run) = service.runs.back_mut() {
+                if run.exit.is_none() && !status.is_active() {
+                    run.exit = Some((SystemTime::now(), status));
                 }
             }
         }
+
+        Ok(())
     }
 
     fn select_service(&mut self) {
         if self.is_table() {
-            if let Some(selected) = self.table.table_state.selected() {
-                self.selected = Some(selected);
+            match self.table.selected_row() {
+                Some(TableRow::Service(index)) => {
+                    self.selected = Some(index);
+                    self.scroll = 0;
+                    self.viewed_run = None;
+                    self.details = None;
+                }
+                Some(TableRow::Group(group)) => self.table.toggle_collapsed(group),
+                None => {}
             }
         }
     }
 
+    fn toggle_details(&mut self) {
+        if self.details.is_some() {
+            self.details = None;
+        } else if let Some(selected) = self.selected {
+            let service = &self.table.services[selected];
+            let env = env::resolve_env(service).map_err(|e| e.to_string());
+            let usage = service.cgroup.as_deref().map(cgroup::usage);
+            self.details = Some(Details { env, usage });
+        }
+    }
+
+    /// Reads what the service in the open details uses from its cgroup
+    fn update_usage(&mut self) {
+        if let (Some(details), Some(selected)) = (&mut self.details, self.selected) {
+            details.usage = self.table.services[selected]
+                .cgroup
+                .as_deref()
+                .map(cgroup::usage);
+        }
+    }
+
+    /// Shows the run before the one that is currently shown in the full view
+    fn previous_run(&mut self) {
+        if let Some(selected) = self.selected {
+            let runs = self.table.services[selected].runs.len();
+            let current = self.viewed_run.unwrap_or(runs.saturating_sub(1));
+
+            self.viewed_run = Some(current.saturating_sub(1));
+            self.scroll = 0;
+        }
+    }
+
+    /// Shows the run after the one that is currently shown in the full view. After the last run,
+    /// the view follows the current run again.
+    fn next_run(&mut self) {
+        if let Some(selected) = self.selected {
+            let runs = self.table.services[selected].runs.len();
+
+            self.viewed_run = self
+                .viewed_run
+                .map(|run| run + 1)
+                .filter(|run| *run + 1 < runs);
+            self.scroll = 0;
+        }
+    }
+
     fn leave_service(&mut self) {
         self.selected = None;
+        self.split = None;
+        self.merged = None;
     }
 
-    fn next(&mut self) {
-        let i = match self.table.table_state.selected() {
-            Some(i) => {
-                if i >= self.table.services.len() - 1 {
-                    0
-                } else {
-                    i + 1
-                }
+    fn toggle_mark(&mut self) {
+        if !self.is_table() {
+            return;
+        }
+
+        let services = self.current_services();
+        let marked = !services
+            .iter()
+            .all(|index| self.table.services[*index].marked);
+
+        for index in services {
+            self.table.services[index].marked = marked;
+        }
+    }
+
+    fn open_split(&mut self) {
+        if !self.is_table() {
+            return;
+        }
+
+        let panes = self
+            .table
+            .services
+            .iter()
+            .enumerate()
+            .filter(|(_, service)| service.marked)
+            .map(|(service, _)| Pane { service, scroll: 0 })
+            .collect::<Vec<_>>();
+
+        if !panes.is_empty() {
+            self.split = Some(SplitView {
+                panes,
+                focused: 0,
+                layout: SplitLayout::Tiled,
+            });
+        }
+    }
+
+    fn toggle_split_layout(&mut self) {
+        if let Some(split) = &mut self.split {
+            split.layout = match split.layout {
+                SplitLayout::Tiled => SplitLayout::Stacked,
+                SplitLayout::Stacked => SplitLayout::Tiled,
+            };
+        }
+    }
+
+    /// Opens the merged view with the output of the current tab, the others can be shown with
+    /// the filter
+    fn open_merged(&mut self) {
+        if self.is_table() {
+            let excluded = self
+                .table
+                .services
+                .iter()
+                .enumerate()
+                .filter(|(_, service)| !self.table.is_in_tab(service))
+                .map(|(index, _)| index)
+                .collect();
+
+            self.merged = Some(MergedView {
+                excluded,
+                ..MergedView::default()
+            });
+        }
+    }
+
+    fn switch_tab(&mut self) {
+        if self.is_table() && self.table.has_tasks() {
+            self.table.tab = match self.table.tab {
+                Tab::Services => Tab::Tasks,
+                Tab::Tasks => Tab::Services,
+            };
+            self.table.table_state.select(Some(0));
+            self.table.table_offset = 0;
+        }
+    }
+
+    fn toggle_merged_filter(&mut self) {
+        if let Some(merged) = &mut self.merged {
+            if !merged.excluded.remove(&merged.cursor) {
+                merged.excluded.insert(merged.cursor);
             }
+        }
+    }
+
+    fn merged_cursor_left(&mut self) {
+        // a config can have no services at all
+        if self.table.services.is_empty() {
+            return;
+        }
+
+        if let Some(merged) = &mut self.merged {
+            merged.cursor = match merged.cursor {
+                0 => self.table.services.len() - 1,
+                i => i - 1,
+            };
+        }
+    }
+
+    fn merged_cursor_right(&mut self) {
+        if self.table.services.is_empty() {
+            return;
+        }
+
+        if let Some(merged) = &mut self.merged {
+            merged.cursor = (merged.cursor + 1) % self.table.services.len();
+        }
+    }
+
+    fn focus_next_pane(&mut self) {
+        if let Some(split) = &mut self.split {
+            split.focused = (split.focused + 1) % split.panes.len();
+        }
+    }
+
+    /// Moves `count` rows down, wrapping around at the end of the table
+    fn next(&mut self, count: usize) {
+        if let Some(scroll) = self.log_scroll() {
+            *scroll = scroll.saturating_sub(count);
+            return;
+        }
+
+        let rows = self.table.rows().len();
+        if rows == 0 || count == 0 {
+            return;
+        }
+
+        let i = match self.table.table_state.selected() {
+            Some(i) => (i + count % rows) % rows,
+            // the first step selects the first row
+            None => (count - 1) % rows,
+        };
+        self.table.table_state.select(Some(i));
+    }
+
+    /// Moves `count` rows up, wrapping around at the start of the table
+    fn previous(&mut self, count: usize) {
+        if let Some(scroll) = self.log_scroll() {
+            *scroll = scroll.saturating_add(count);
+            return;
+        }
+
+        let rows = self.table.rows().len();
+        if rows == 0 || count == 0 {
+            return;
+        }
+
+        let i = match self.table.table_state.selected() {
+            Some(i) => (i + rows - count % rows) % rows,
+            // the first step selects the first row
+            None => (rows - (count - 1) % rows) % rows,
+        };
+        self.table.table_state.select(Some(i));
+    }
+
+    fn page_down(&mut self, lines: usize) {
+        if let Some(scroll) = self.log_scroll() {
+            *scroll = scroll.saturating_sub(lines);
+            return;
+        }
+
+        let last = self.table.rows().len().saturating_sub(1);
+        let i = match self.table.table_state.selected() {
+            Some(i) => i.saturating_add(lines).min(last),
             None => 0,
         };
         self.table.table_state.select(Some(i));
     }
 
-    fn previous(&mut self) {
+    fn page_up(&mut self, lines: usize) {
+        if let Some(scroll) = self.log_scroll() {
+            *scroll = scroll.saturating_add(lines);
+            return;
+        }
+
         let i = match self.table.table_state.selected() {
-            Some(i) => {
-                if i == 0 {
-                    self.table.services.len() - 1
-                } else {
-                    i - 1
-                }
-            }
+            Some(i) => i.saturating_sub(lines),
             None => 0,
         };
         self.table.table_state.select(Some(i));
     }
 
-    fn run_service(&mut self) -> SmResult {
-        let index = self.selected.or_else(|| self.table.table_state.selected());
+    fn top(&mut self) {
+        match self.log_scroll() {
+            // the view clamps the scroll to the first line
+            Some(scroll) => *scroll = usize::MAX,
+            None => self.table.table_state.select(Some(0)),
+        }
+    }
 
-        if let Some(index) = index {
-            let status = {
-                let service = &mut self.table.services[index];
-                service.std_io_buf.clear();
-                *service.status.lock()?
-            };
+    fn bottom(&mut self) {
+        match self.log_scroll() {
+            Some(scroll) => *scroll = 0,
+            None => {
+                let last = self.table.rows().len().saturating_sub(1);
+                self.table.table_state.select(Some(last));
+            }
+        }
+    }
+
+    /// The scroll position of the log that is currently focused, if a log is shown
+    fn log_scroll(&mut self) -> Option<&mut usize> {
+        match &mut self.split {
+            Some(split) => split
+                .panes
+                .get_mut(split.focused)
+                .map(|pane| &mut pane.scroll),
+            None if self.selected.is_some() => Some(&mut self.scroll),
+            None => None,
+        }
+    }
 
-            if status != ServiceStatus::Running {
+    fn run_services(&mut self, indices: Vec<usize>) -> SmResult {
+        for index in indices {
+            let service = &mut self.table.services[index];
+            let status = { *service.status.lock()? };
+
+            if !status.is_active() {
                 self.start_service(index)?;
             }
         }
@@ -167,63 +633,261 @@ impl App {
         Ok(())
     }
 
-    fn kill_service(&mut self) -> SmResult {
-        let index = self.selected.or_else(|| self.table.table_state.selected());
-
-        if let Some(index) = index {
+    fn kill_services(&mut self, indices: Vec<usize>) -> SmResult {
+        for index in indices {
             let service = &mut self.table.services[index];
 
             let status = { *service.status.lock()? };
 
-            if status == ServiceStatus::Running {
+            // nothing has been started yet while it waits for its dependencies
+            if status == ServiceStatus::Waiting {
+                info!(name = %service.name, "Cancelling waiting service");
+                *service.status.lock()? = ServiceStatus::Killed;
+            }
+
+            if matches!(status, ServiceStatus::Starting | ServiceStatus::Running) {
                 info!(name = %service.name,"Killing service");
 
                 let terminate_sender = &mut self
                     .thread_terminates
                     .get(&index)
                     .ok_or(SmError::Bug("Child termination channel not found"))?;
-                terminate_sender.send(()).map_err(|_| {
-                    SmError::Bug("Failed to send termination signal to child process")
-                })?;
+                // the worker thread ends right after the service stopped on its own
+                if terminate_sender.send(()).is_err() {
+                    info!(name = %service.name, "Service already stopped");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops the services and starts them again once their worker thread reported that they
+    /// exited.
+    fn restart_services(&mut self, indices: Vec<usize>) -> SmResult {
+        self.kill_services(indices.clone())?;
+        self.pending_restarts.extend(indices);
+        self.start_pending_restarts()
+    }
+
+    fn start_pending_restarts(&mut self) -> SmResult {
+        let mut stopped = Vec::new();
+        for index in &self.pending_restarts {
+            if !self.table.services[*index].status.lock()?.is_active() {
+                stopped.push(*index);
+            }
+        }
+
+        for index in stopped {
+            self.pending_restarts.remove(&index);
+
+            self.start_service(index)?;
+
+            if let Some(run) = self.table.services[index].runs.back_mut() {
+                let started = run.started;
+                run.output.push_line(
+                    format!("--- restarted at {} ---", view::clock_time(started)),
+                    started,
+                );
             }
         }
 
         Ok(())
     }
 
+    /// Starts a new run of the service. If its dependencies aren't ready yet, they are started
+    /// and the service waits for them.
     fn start_service(&mut self, index: usize) -> SmResult {
         let service = &mut self.table.services[index];
 
         info!(name = %service.name, "Starting service");
 
-        *service.status.lock()? = ServiceStatus::Running;
+        let started = SystemTime::now();
+        service.runs.push_back(Run {
+            started,
+            exit: None,
+            output: Output::default(),
+        });
+        while service.runs.len() > MAX_RUNS {
+            service.runs.pop_front();
+
+            // the full view keeps showing the same run, or the oldest one if that was dropped
+            if self.selected == Some(index) {
+                self.viewed_run = self.viewed_run.map(|run| run.saturating_sub(1));
+            }
+        }
+
+        let mut waiting_for = Vec::new();
+        for name in self.table.services[index].depends_on.clone() {
+            let dependency = match self.table.service_index(&name) {
+                Some(dependency) => dependency,
+                None => continue,
+            };
+
+            let dependency_status = *self.table.services[dependency].status.lock()?;
+            if !self.table.services[dependency].is_ready(dependency_status) {
+                if !dependency_status.is_active() {
+                    self.start_service(dependency)?;
+                }
+                waiting_for.push(name);
+            }
+        }
+
+        let service = &mut self.table.services[index];
+
+        if waiting_for.is_empty() {
+            return self.spawn_service(index);
+        }
+
+        *service.status.lock()? = ServiceStatus::Waiting;
+        if let Some(run) = service.runs.back_mut() {
+            run.output.push_line(
+                format!("--- waiting for {} ---", waiting_for.join(", ")),
+                started,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Starts the services whose dependencies are ready, or fails them if a dependency failed
+    fn start_waiting_services(&mut self) -> SmResult {
+        for index in 0..self.table.services.len() {
+            let service = &self.table.services[index];
+            if *service.status.lock()? != ServiceStatus::Waiting {
+                continue;
+            }
+
+            let mut ready = true;
+            for name in &service.depends_on {
+                let dependency = match self.table.service_index(name) {
+                    Some(dependency) => &self.table.services[dependency],
+                    None => continue,
+                };
+
+                let dependency_status = *dependency.status.lock()?;
+                if dependency.is_ready(dependency_status) {
+                    continue;
+                }
+
+                if !dependency_status.is_active() {
+                    let msg = format!("dependency `{}` stopped: {}", name, dependency_status);
+                    fail_start(service, &service.stdout.send, &msg)?;
+                }
+                ready = false;
+                break;
+            }
+
+            if ready {
+                self.spawn_service(index)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts the services whose next scheduled run is due. A run is skipped if the last one is
+    /// still going.
+    fn start_scheduled_services(&mut self) -> SmResult {
+        let now = SystemTime::now();
+
+        for index in 0..self.table.services.len() {
+            let service = &mut self.table.services[index];
+
+            let schedule = match (&service.schedule, service.next_run) {
+                (Some(schedule), Some(next_run)) if next_run <= now => schedule,
+                _ => continue,
+            };
+            service.next_run = schedule.next_after(now);
+
+            if service.status.lock()?.is_active() {
+                info!(name = %service.name, "Skipping scheduled run, the last one is still active");
+                continue;
+            }
+
+            self.start_service(index)?;
+        }
 
-        let mut cmd = Command::new("sh");
+        Ok(())
+    }
 
-        cmd.args(["-c", &service.command]);
-        cmd.envs(service.env.iter());
+    /// Spawns the child process of the current run of the service
+    fn spawn_service(&mut self, index: usize) -> SmResult {
+        let service = &mut self.table.services[index];
 
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-        cmd.stdin(Stdio::piped());
+        // the worker sets it to running once the command has been spawned
+        *service.status.lock()? = ServiceStatus::Starting;
 
         let stdout_send = service.stdout.send.clone();
 
-        let child = match cmd.spawn() {
+        let vars = match env::resolve_env(service) {
+            Ok(vars) => vars,
             Err(err) => {
-                let mut buf = [0; STDIO_SEND_BUF_SIZE];
-
-                let bytes = err.to_string();
+                let msg = format!("failed to load environment: {}", err);
+                return fail_start(service, &stdout_send, &msg);
+            }
+        };
 
-                (&mut buf[..]).write_all(bytes.as_bytes())?;
+        let mut command = match &service.command {
+            CommandLine::Shell(script) => shell_command(script, service, &vars),
+            CommandLine::Argv(argv) => {
+                let mut cmd = Command::new(&argv[0]);
+                cmd.args(&argv[1..]);
+                prepare_command(cmd, service, &vars)
+            }
+        };
+        command.stdin(Stdio::piped());
 
-                stdout_send
-                    .send((buf, bytes.len()))
-                    .map_err(|_| SmError::FailedToSendStdio)?;
+        service.cgroup = None;
+        if let Some(cgroups) = &self.cgroups {
+            let created = cgroups
+                .create(&service.name, service.memory_max.as_ref(), service.cpu_max)
+                .and_then(|(cgroup, messages)| {
+                    cgroup::move_into(&mut command, &cgroup)?;
+                    Ok((cgroup, messages))
+                });
 
-                return Err(SmError::FailedToStartChild(err));
+            match created {
+                Ok((cgroup, messages)) => {
+                    for msg in messages {
+                        send_message(&stdout_send, &format!("--- {} ---\n", msg))?;
+                    }
+                    service.cgroup = Some(cgroup);
+                }
+                Err(err) => {
+                    info!(error = %err, name = %service.name, "Failed to create cgroup");
+                    let msg = format!("--- not running in a cgroup: {} ---\n", err);
+                    send_message(&stdout_send, &msg)?;
+                }
             }
-            Ok(child) => child,
+        }
+
+        let hooks = |commands: &[String]| {
+            commands
+                .iter()
+                .map(|command| {
+                    let mut process = shell_command(command, service, &vars);
+                    process.stdin(Stdio::null());
+                    if let Some(cgroup) = &service.cgroup {
+                        // can't fail, the command was moved into the same cgroup already
+                        let _ = cgroup::move_into(&mut process, cgroup);
+                    }
+                    Hook {
+                        command: command.clone(),
+                        process,
+                    }
+                })
+                .collect()
+        };
+
+        let launch = Launch {
+            command,
+            pre_start: hooks(&service.hooks.pre_start),
+            post_start: hooks(&service.hooks.post_start),
+            pre_stop: hooks(&service.hooks.pre_stop),
+            post_stop: hooks(&service.hooks.post_stop),
+            oneshot: service.kind == ServiceKind::Oneshot,
+            cgroup: service.cgroup.clone(),
         };
 
         let (terminate_send, terminate_recv) = mpsc::channel();
@@ -237,7 +901,7 @@ impl App {
             .name(format!("worker-({})", service.name))
             .spawn(move || {
                 match child_process_thread(
-                    child,
+                    launch,
                     stdout_send,
                     service_status,
                     service_name,
@@ -250,10 +914,299 @@ impl App {
                 }
             });
 
-        if let Err(err) = spawn_result {
-            error!(error = %err, "Error spawning thread");
+        match spawn_result {
+            Ok(worker) => {
+                self.workers.insert(index, worker);
+            }
+            Err(err) => {
+                error!(error = %err, "Error spawning thread");
+            }
         }
 
         Ok(())
     }
 }
+
+/// Creates the command that runs `script` with the shell of the service
+fn shell_command(script: &str, service: &Service, vars: &BTreeMap<String, String>) -> Command {
+    let mut cmd = Command::new(&service.shell[0]);
+    cmd.args(&service.shell[1..]);
+    cmd.arg(script);
+
+    prepare_command(cmd, service, vars)
+}
+
+/// Sets up the command to run in the workdir and environment and with the limits of the service
+fn prepare_command(
+    mut cmd: Command,
+    service: &Service,
+    vars: &BTreeMap<String, String>,
+) -> Command {
+    cmd.env_clear();
+    cmd.envs(vars);
+    cmd.current_dir(&service.workdir);
+
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    // the service gets its own process group so that everything it spawns can be stopped
+    cmd.process_group(0);
+
+    apply_limits(
+        &mut cmd,
+        &service.limits,
+        service.nice,
+        service.oom_score_adj,
+    );
+
+    cmd
+}
+
+fn new_service(
+    name: String,
+    service: config::Service,
+    task: bool,
+    global_env_files: &[PathBuf],
+    global_inherit_env: &InheritEnv,
+    global_shell: Option<&str>,
+) -> io::Result<Service> {
+    let (stdout_send, stdout_recv) = mpsc::channel();
+
+    let workdir = service
+        .workdir
+        .ok_or_else(|| io::Error::from(ErrorKind::Other))
+        .or_else(|_| std::env::current_dir())?;
+
+    let env_files = global_env_files
+        .iter()
+        .cloned()
+        .chain(
+            service
+                .env_file
+                .unwrap_or_default()
+                .into_iter()
+                .map(|path| workdir.join(path)),
+        )
+        .collect();
+
+    // the loader made sure that the schedule is valid
+    let schedule = Schedule::from_config(service.schedule.as_deref(), service.every.as_deref())
+        .ok()
+        .flatten();
+
+    // scheduled services are usually jobs that are expected to exit
+    let kind = match (service.kind, &schedule) {
+        (Some(kind), _) => kind,
+        (None, Some(_)) => ServiceKind::Oneshot,
+        (None, None) => ServiceKind::default(),
+    };
+
+    Ok(Service {
+        // the loader makes sure that every service has a command
+        command: service.command.unwrap_or_default(),
+        shell: config::shell_args(service.shell.as_deref().or(global_shell)),
+        name,
+        workdir,
+        env: service.env.unwrap_or_default(),
+        env_files,
+        inherit_env: service
+            .inherit_env
+            .unwrap_or_else(|| global_inherit_env.clone()),
+        status: Arc::new(Mutex::new(ServiceStatus::NotStarted)),
+        runs: VecDeque::new(),
+        stdout: StdIoStream {
+            recv: stdout_recv,
+            send: stdout_send,
+        },
+        marked: false,
+        group: service.group,
+        task,
+        kind,
+        depends_on: service.depends_on.unwrap_or_default(),
+        hooks: Hooks {
+            pre_start: service.pre_start.unwrap_or_default(),
+            post_start: service.post_start.unwrap_or_default(),
+            pre_stop: service.pre_stop.unwrap_or_default(),
+            post_stop: service.post_stop.unwrap_or_default(),
+        },
+        limits: service.limits.unwrap_or_default(),
+        nice: service.nice,
+        oom_score_adj: service.oom_score_adj,
+        memory_max: service.memory_max,
+        cpu_max: service.cpu_max,
+        cgroup: None,
+        next_run: schedule
+            .as_ref()
+            .and_then(|schedule| schedule.next_after(SystemTime::now())),
+        schedule,
+    })
+}
+
+/// Shows why a service couldn't be started in its output
+fn fail_start(service: &Service, stdout_send: &mpsc::Sender<StdioSendBuf>, msg: &str) -> SmResult {
+    *service.status.lock()? = ServiceStatus::FailedToStart;
+
+    send_message(stdout_send, &format!("{}\n", msg))
+}
+
+impl Output {
+    /// All lines of the output, including the partial line at the end
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &OutputLine> {
+        self.lines.iter().chain(self.partial.iter())
+    }
+
+    /// Appends raw output of the child, splitting it into lines. Lines that aren't terminated yet
+    /// are kept as the partial line until the rest of them arrives.
+    fn push(&mut self, bytes: &[u8], time: SystemTime) {
+        let text = String::from_utf8_lossy(bytes);
+        let mut parts = text.split('\n').peekable();
+
+        while let Some(part) = parts.next() {
+            let line = match self.partial.take() {
+                Some(mut partial) => {
+                    partial.text.push_str(part);
+                    partial
+                }
+                None => OutputLine {
+                    time,
+                    text: part.to_string(),
+                },
+            };
+
+            if parts.peek().is_some() {
+                self.lines.push_back(line);
+            } else if !line.text.is_empty() {
+                self.partial = Some(line);
+            }
+        }
+
+        self.truncate();
+    }
+
+    /// Appends a line that didn't come from the child, like a message of the service manager
+    fn push_line(&mut self, text: String, time: SystemTime) {
+        self.lines.extend(self.partial.take());
+        self.lines.push_back(OutputLine { time, text });
+        self.truncate();
+    }
+
+    fn truncate(&mut self) {
+        while self.lines.len() > MAX_OUTPUT_LINES {
+            self.lines.pop_front();
+        }
+    }
+}
+
+impl Hitboxes {
+    fn row_at(&self, column: u16, row: u16) -> Option<usize> {
+        find_hitbox(&self.rows, column, row)
+    }
+
+    fn pane_at(&self, column: u16, row: u16) -> Option<usize> {
+        find_hitbox(&self.panes, column, row)
+    }
+
+    fn hint_at(&self, column: u16, row: u16) -> Option<Action> {
+        find_hitbox(&self.hints, column, row)
+    }
+}
+
+fn find_hitbox<T: Copy>(hitboxes: &[(Rect, T)], column: u16, row: u16) -> Option<T> {
+    hitboxes
+        .iter()
+        .find(|(area, _)| {
+            (area.x..area.x + area.width).contains(&column)
+                && (area.y..area.y + area.height).contains(&row)
+        })
+        .map(|(_, value)| *value)
+}
+
+impl AppState {
+    /// The rows of the current tab, with a header in front of every group. The services of
+    /// collapsed groups are left out.
+    pub fn rows(&self) -> Vec<TableRow> {
+        let mut rows = Vec::new();
+        let mut current_group = None;
+
+        for (index, service) in self.services.iter().enumerate() {
+            if !self.is_in_tab(service) {
+                continue;
+            }
+
+            if let Some(group) = &service.group {
+                if current_group != Some(group) {
+                    rows.push(TableRow::Group(group.clone()));
+                    current_group = Some(group);
+                }
+
+                if self.collapsed.contains(group) {
+                    continue;
+                }
+            }
+
+            rows.push(TableRow::Service(index));
+        }
+
+        rows
+    }
+
+    pub fn is_in_tab(&self, service: &Service) -> bool {
+        service.task == (self.tab == Tab::Tasks)
+    }
+
+    pub fn has_tasks(&self) -> bool {
+        self.services.iter().any(|service| service.task)
+    }
+
+    fn selected_row(&self) -> Option<TableRow> {
+        self.table_state
+            .selected()
+            .and_then(|selected| self.rows().into_iter().nth(selected))
+    }
+
+    fn toggle_collapsed(&mut self, group: String) {
+        if !self.collapsed.remove(&group) {
+            self.collapsed.insert(group);
+        }
+    }
+
+    fn service_index(&self, name: &str) -> Option<usize> {
+        self.services
+            .iter()
+            .position(|service| service.name == name)
+    }
+
+    pub fn group_services(&self, group: &str) -> Vec<usize> {
+        self.services
+            .iter()
+            .enumerate()
+            .filter(|(_, service)| service.group.as_deref() == Some(group))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+impl Service {
+    /// Whether the services that depend on this one can be started. Oneshot services have to
+    /// complete first, the others only have to be running.
+    fn is_ready(&self, status: ServiceStatus) -> bool {
+        match self.kind {
+            ServiceKind::Simple => status == ServiceStatus::Running,
+            ServiceKind::Oneshot => status == ServiceStatus::Completed,
+        }
+    }
+}
+
+impl ServiceStatus {
+    /// Whether the service has been started and hasn't exited yet, which includes waiting for
+    /// its dependencies
+    pub fn is_active(self) -> bool {
+        matches!(
+            self,
+            ServiceStatus::Waiting
+                | ServiceStatus::Starting
+                | ServiceStatus::Running
+                | ServiceStatus::Stopping
+        )
+    }
+}
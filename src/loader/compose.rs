@@ -0,0 +1,304 @@
+use crate::model::SmError;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use toml::value::Table;
+use toml::Value;
+
+/// The names docker compose looks for, in order
+pub const COMPOSE_FILES: &[&str] = &[
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+];
+
+#[derive(Debug, Deserialize)]
+struct ComposeFile {
+    #[serde(default)]
+    services: BTreeMap<String, ComposeService>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComposeService {
+    entrypoint: Option<Command>,
+    command: Option<Command>,
+    environment: Option<Environment>,
+    env_file: Option<OneOrMany>,
+    depends_on: Option<DependsOn>,
+    working_dir: Option<String>,
+    healthcheck: Option<serde_yaml::Value>,
+    /// Everything that has no equivalent, like `image` or `ports`
+    #[serde(flatten)]
+    other: BTreeMap<String, serde_yaml::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Command {
+    Shell(String),
+    Exec(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Environment {
+    Map(BTreeMap<String, Option<serde_yaml::Value>>),
+    List(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum DependsOn {
+    List(Vec<String>),
+    /// The long syntax with conditions, like `db: { condition: service_healthy }`
+    Map(BTreeMap<String, serde_yaml::Value>),
+}
+
+/// Converts the services of a docker-compose file to a config. Services that only run an image
+/// are left out. Everything that couldn't be translated is returned as a warning and also noted
+/// as a comment in the config.
+pub fn import_compose(path: &Path) -> Result<(String, Vec<String>), SmError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| SmError::InvalidConfig(format!("failed to read {}: {}", path.display(), e)))?;
+
+    let compose = serde_yaml::from_str::<ComposeFile>(&content)
+        .map_err(|e| SmError::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+
+    let mut config = format!(
+        "# Converted from {}\n",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let mut warnings = Vec::new();
+
+    // services that others wait for to complete are what oneshot services are in the config
+    let oneshots = compose
+        .services
+        .values()
+        .filter_map(|service| match &service.depends_on {
+            Some(DependsOn::Map(dependencies)) => Some(dependencies),
+            _ => None,
+        })
+        .flatten()
+        .filter(|(_, dependency)| {
+            dependency.get("condition").and_then(|c| c.as_str())
+                == Some("service_completed_successfully")
+        })
+        .map(|(name, _)| name.clone())
+        .collect::<HashSet<_>>();
+
+    let skipped = compose
+        .services
+        .iter()
+        .filter(|(_, service)| service.entrypoint.is_none() && service.command.is_none())
+        .map(|(name, _)| name.clone())
+        .collect::<HashSet<_>>();
+
+    for (name, service) in compose.services {
+        let oneshot = oneshots.contains(&name);
+        let (table, notes) = convert_service(service, oneshot, &skipped);
+
+        warnings.extend(notes.iter().map(|note| format!("{}: {}", name, note)));
+
+        config.push('\n');
+        for note in &notes {
+            let _ = writeln!(config, "# {}", note);
+        }
+
+        let table = match table {
+            Some(table) => table,
+            None => continue,
+        };
+
+        let mut service = Table::new();
+        service.insert(name, Value::Table(table));
+        let service = toml::to_string(&service)
+            .map_err(|_| SmError::Bug("Converted service could not be serialized"))?;
+        config.push_str(&service);
+    }
+
+    Ok((config, warnings))
+}
+
+/// Converts a service to a service table and notes about what couldn't be translated. There is
+/// no table if the service has nothing to run locally, which is what the `skipped` services are.
+fn convert_service(
+    service: ComposeService,
+    oneshot: bool,
+    skipped: &HashSet<String>,
+) -> (Option<Table>, Vec<String>) {
+    let mut notes = Vec::new();
+    let mut table = Table::new();
+
+    let ignored = service
+        .other
+        .keys()
+        .map(|key| format!("`{}`", key))
+        .collect::<Vec<_>>();
+    if !ignored.is_empty() {
+        notes.push(format!("not translated: {}", ignored.join(", ")));
+    }
+
+    // the command of a container is appended to its entrypoint
+    let parts = [service.entrypoint, service.command]
+        .into_iter()
+        .flatten()
+        .filter(|part| match part {
+            Command::Shell(command) => !command.is_empty(),
+            Command::Exec(args) => !args.is_empty(),
+        })
+        .collect::<Vec<_>>();
+
+    if parts.is_empty() {
+        notes.push("skipped, it has no command and only runs what its image defines".to_string());
+        return (None, notes);
+    }
+
+    // exec forms are run without a shell, like in the container
+    let command = if parts.iter().all(|part| matches!(part, Command::Exec(_))) {
+        let args = parts.into_iter().flat_map(|part| match part {
+            Command::Exec(args) => args,
+            Command::Shell(_) => Vec::new(),
+        });
+        Value::Array(args.map(Value::String).collect())
+    } else {
+        let command = parts
+            .into_iter()
+            .map(|part| match part {
+                Command::Shell(command) => command,
+                Command::Exec(args) => args
+                    .iter()
+                    .map(|arg| shell_quote(arg))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        Value::String(command)
+    };
+    table.insert("command".to_string(), command);
+
+    if oneshot {
+        table.insert("type".to_string(), Value::String("oneshot".to_string()));
+    }
+
+    // the compose file is next to the config, so relative paths like the env files still work
+    let workdir = match service.working_dir {
+        Some(dir) if Path::new(&dir).is_relative() => format!("${{config_dir}}/{}", dir),
+        Some(dir) => {
+            notes.push(format!(
+                "`working_dir` {} is inside the container, the config directory is used instead",
+                dir
+            ));
+            "${config_dir}".to_string()
+        }
+        None => "${config_dir}".to_string(),
+    };
+    table.insert("workdir".to_string(), Value::String(workdir));
+
+    if let Some(env_file) = service.env_file {
+        let env_files = match env_file {
+            OneOrMany::One(path) => vec![path],
+            OneOrMany::Many(paths) => paths,
+        };
+        table.insert(
+            "env_file".to_string(),
+            Value::Array(env_files.into_iter().map(Value::String).collect()),
+        );
+    }
+
+    let env = match service.environment {
+        // variables without a value are taken from the environment, which services inherit
+        Some(Environment::Map(vars)) => vars
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, yaml_scalar(value?)?)))
+            .collect(),
+        Some(Environment::List(vars)) => vars
+            .iter()
+            .filter_map(|var| var.split_once('='))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        None => Vec::new(),
+    };
+    if !env.is_empty() {
+        let env = env
+            .into_iter()
+            .map(|(name, value)| (name, Value::String(value)))
+            .collect();
+        table.insert("env".to_string(), Value::Table(env));
+    }
+
+    if let Some(depends_on) = service.depends_on {
+        let names = match depends_on {
+            DependsOn::List(names) => names,
+            DependsOn::Map(dependencies) => {
+                for (name, dependency) in &dependencies {
+                    let condition = dependency.get("condition").and_then(|c| c.as_str());
+                    if condition == Some("service_healthy") && !skipped.contains(name) {
+                        notes.push(format!(
+                            "`depends_on` only waits until {} is running, not until it is healthy",
+                            name
+                        ));
+                    }
+                }
+                dependencies.into_keys().collect()
+            }
+        };
+
+        let (skipped, names) = names
+            .into_iter()
+            .partition::<Vec<_>, _>(|name| skipped.contains(name));
+
+        if !skipped.is_empty() {
+            notes.push(format!(
+                "`depends_on` {} is skipped, start it first",
+                skipped.join(", ")
+            ));
+        }
+        if !names.is_empty() {
+            table.insert(
+                "depends_on".to_string(),
+                Value::Array(names.into_iter().map(Value::String).collect()),
+            );
+        }
+    }
+
+    if service.healthcheck.is_some() {
+        notes.push("`healthcheck` isn't supported".to_string());
+    }
+
+    (Some(table), notes)
+}
+
+/// The value of an environment variable, which compose allows to be a number or a boolean
+fn yaml_scalar(value: serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(value) => Some(value),
+        serde_yaml::Value::Number(value) => Some(value.to_string()),
+        serde_yaml::Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Quotes an argument for the shell the command is run with, if necessary
+fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c));
+
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
@@ -0,0 +1,114 @@
+/// What a reference to a variable is replaced with
+pub enum Expansion {
+    Value(String),
+    /// The reference is left as it is, including its default, for the shell of the service
+    Keep,
+    Unset,
+}
+
+impl From<Option<String>> for Expansion {
+    fn from(value: Option<String>) -> Self {
+        match value {
+            Some(value) => Expansion::Value(value),
+            None => Expansion::Unset,
+        }
+    }
+}
+
+/// Replaces `${NAME}` and `${NAME:-default}` in `input` with the value `lookup` returns for
+/// `NAME`. The default is used if the variable is unset or empty. References to unknown variables
+/// without a default are left as they are, so that the shell of the service can still expand
+/// them. `$${` is an escaped `${`.
+pub fn interpolate(input: &str, lookup: impl Fn(&str) -> Expansion) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = escaped;
+            continue;
+        }
+
+        let reference = rest.strip_prefix("${").and_then(|inner| {
+            inner
+                .find('}')
+                .map(|end| (&inner[..end], &inner[end + 1..]))
+        });
+
+        match reference {
+            Some((reference, after)) => {
+                let (name, default) = match reference.split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (reference, None),
+                };
+
+                match (lookup(name), default) {
+                    (Expansion::Value(value), Some(default)) if value.is_empty() => {
+                        output.push_str(default)
+                    }
+                    (Expansion::Value(value), _) => output.push_str(&value),
+                    (Expansion::Unset, Some(default)) => output.push_str(default),
+                    (Expansion::Keep, _) | (Expansion::Unset, None) => {
+                        output.push_str("${");
+                        output.push_str(reference);
+                        output.push('}');
+                    }
+                }
+
+                rest = after;
+            }
+            None => {
+                output.push('$');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{interpolate, Expansion};
+
+    fn lookup(name: &str) -> Expansion {
+        match name {
+            "NAME" => Expansion::Value("api".to_string()),
+            "EMPTY" => Expansion::Value(String::new()),
+            "counter" | "1" => Expansion::Keep,
+            _ => Expansion::Unset,
+        }
+    }
+
+    #[test]
+    fn values_and_defaults() {
+        assert_eq!(interpolate("run ${NAME} now", lookup), "run api now");
+        assert_eq!(interpolate("${NAME:-other}", lookup), "api");
+        assert_eq!(interpolate("${EMPTY:-default}", lookup), "default");
+        assert_eq!(interpolate("${MISSING:-default}", lookup), "default");
+    }
+
+    #[test]
+    fn unknown_without_default_is_kept() {
+        assert_eq!(interpolate("echo ${MISSING}", lookup), "echo ${MISSING}");
+    }
+
+    #[test]
+    fn kept_references_keep_their_default() {
+        assert_eq!(
+            interpolate("counter=5\necho ${counter:-0} ${1:-x}", lookup),
+            "counter=5\necho ${counter:-0} ${1:-x}"
+        );
+    }
+
+    #[test]
+    fn escapes_and_plain_dollars() {
+        assert_eq!(interpolate("$${NAME} $NAME $", lookup), "${NAME} $NAME $");
+        assert_eq!(interpolate("${unclosed", lookup), "${unclosed");
+    }
+}
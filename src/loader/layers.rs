@@ -0,0 +1,360 @@
+use crate::loader::procfile::{is_procfile, procfile_services};
+use crate::model::SmError;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::Table;
+use toml::Value;
+
+/// A config file that has been merged into the config
+#[derive(Debug)]
+pub struct Source {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+/// The result of merging the main config file, its includes and the local override file
+#[derive(Debug, Default)]
+pub struct Layers {
+    pub table: Table,
+    pub sources: Vec<Source>,
+    /// The index of the source that set every key path last
+    origins: HashMap<Vec<String>, usize>,
+}
+
+impl Layers {
+    /// Reads the Procfile imported with `import`, the main config file, the files it includes
+    /// with `include` and the `.local.toml` file next to it, in that order. Later files are
+    /// deep-merged over earlier ones. If the main file is a Procfile, it is the only file.
+    pub fn load(path: &Path, config_dir: &Path) -> Result<Layers, SmError> {
+        let mut layers = Layers::default();
+
+        if is_procfile(path) {
+            let (source, table) = layers.read_procfile(path, config_dir)?;
+            layers.merge(source, table);
+            return Ok(layers);
+        }
+
+        let (main_source, main) = layers.read(path)?;
+        let import = main.get("import").cloned();
+        let includes = main.get("include").cloned();
+
+        // the services of the Procfile can be adjusted in the config
+        if let Some(import) = import {
+            let import = match import {
+                Value::String(import) => config_dir.join(import),
+                _ => {
+                    return Err(SmError::InvalidConfig(format!(
+                        "{}: `import` must be the path of a Procfile",
+                        path.display()
+                    )))
+                }
+            };
+            let dir = import.parent().unwrap_or(config_dir);
+            let (source, table) = layers.read_procfile(&import, dir)?;
+            layers.merge(source, table);
+        }
+
+        layers.merge(main_source, main);
+
+        for pattern in include_patterns(includes, path)? {
+            for include in expand_pattern(&config_dir.join(pattern))? {
+                let (source, table) = layers.read(&include)?;
+                layers.check_not_nested(source, &table)?;
+                layers.merge(source, table);
+            }
+        }
+
+        let local_path = local_override_path(path);
+        if local_path.exists() {
+            let (source, table) = layers.read(&local_path)?;
+            layers.check_not_nested(source, &table)?;
+            layers.merge(source, table);
+        }
+
+        layers.table.remove("import");
+        layers.table.remove("include");
+
+        Ok(layers)
+    }
+
+    /// Reads a config file and returns its index in the sources together with its content
+    fn read(&mut self, path: &Path) -> Result<(usize, Table), SmError> {
+        let text = read_file(path)?;
+
+        let table = toml::from_str::<Table>(&text)
+            .map_err(|e| SmError::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+
+        Ok((self.add_source(path, text), table))
+    }
+
+    /// Includes and imports are only resolved in the main file, so they would be ignored in the
+    /// other files
+    fn check_not_nested(&self, source: usize, table: &Table) -> Result<(), SmError> {
+        let source = &self.sources[source];
+
+        match ["include", "import"]
+            .iter()
+            .find(|key| table.contains_key(**key))
+        {
+            Some(key) => Err(SmError::InvalidConfig(format!(
+                "{}: `{}` is only allowed in the main config file",
+                source.location(&[key.to_string()]),
+                key
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn read_procfile(&mut self, path: &Path, dir: &Path) -> Result<(usize, Table), SmError> {
+        let text = read_file(path)?;
+
+        let table = procfile_services(dir, &text).map_err(|(line, msg)| {
+            SmError::InvalidConfig(format!("{}:{}: {}", path.display(), line, msg))
+        })?;
+
+        Ok((self.add_source(path, text), table))
+    }
+
+    fn add_source(&mut self, path: &Path, text: String) -> usize {
+        self.sources.push(Source {
+            path: path.to_path_buf(),
+            text,
+        });
+        self.sources.len() - 1
+    }
+
+    fn merge(&mut self, source: usize, table: Table) {
+        merge_table(
+            &mut self.table,
+            table,
+            &mut Vec::new(),
+            source,
+            &mut self.origins,
+        );
+    }
+
+    /// Prefixes the message with the location of the value at `path`
+    pub fn problem(&self, path: &[&str], msg: String) -> String {
+        let path = path.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+
+        match self.location(&path) {
+            Some(location) => format!("{}: {}", location, msg),
+            None => msg,
+        }
+    }
+
+    /// Describes where the value at `path` was set, like `services/api.toml:12:1`
+    pub fn location(&self, path: &[String]) -> Option<String> {
+        // the path of an error can point below the value that was actually set, like an index
+        // into an array
+        let (source, path) = (0..=path.len()).rev().find_map(|len| {
+            self.origins
+                .get(&path[..len])
+                .map(|source| (&self.sources[*source], &path[..len]))
+        })?;
+
+        Some(source.location(path))
+    }
+}
+
+impl Source {
+    /// The position of the key path in the file, or just the file if it isn't found
+    fn location(&self, path: &[String]) -> String {
+        match find_position(&self.text, path) {
+            Some((line, column)) => format!("{}:{}:{}", self.path.display(), line, column),
+            None => self.path.display().to_string(),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, SmError> {
+    fs::read_to_string(path)
+        .map_err(|e| SmError::InvalidConfig(format!("failed to read {}: {}", path.display(), e)))
+}
+
+fn merge_table(
+    target: &mut Table,
+    table: Table,
+    path: &mut Vec<String>,
+    source: usize,
+    origins: &mut HashMap<Vec<String>, usize>,
+) {
+    for (key, value) in table {
+        path.push(key.clone());
+        origins.insert(path.clone(), source);
+
+        match (target.get_mut(&key), value) {
+            (Some(Value::Table(target)), Value::Table(table)) => {
+                merge_table(target, table, path, source, origins);
+            }
+            (_, value) => {
+                record_origins(&value, path, source, origins);
+                target.insert(key, value);
+            }
+        }
+
+        path.pop();
+    }
+}
+
+/// Records the source of all keys below a value that replaced the previous one
+fn record_origins(
+    value: &Value,
+    path: &mut Vec<String>,
+    source: usize,
+    origins: &mut HashMap<Vec<String>, usize>,
+) {
+    if let Value::Table(table) = value {
+        for (key, value) in table {
+            path.push(key.clone());
+            origins.insert(path.clone(), source);
+            record_origins(value, path, source, origins);
+            path.pop();
+        }
+    }
+}
+
+fn include_patterns(includes: Option<Value>, path: &Path) -> Result<Vec<String>, SmError> {
+    let invalid = || {
+        SmError::InvalidConfig(format!(
+            "{}: `include` must be a list of paths",
+            path.display()
+        ))
+    };
+
+    match includes {
+        None => Ok(Vec::new()),
+        Some(Value::Array(patterns)) => patterns
+            .into_iter()
+            .map(|pattern| match pattern {
+                Value::String(pattern) => Ok(pattern),
+                _ => Err(invalid()),
+            })
+            .collect(),
+        Some(_) => Err(invalid()),
+    }
+}
+
+/// Finds the files matching a path whose file name may contain `*` and `?`, sorted by name
+fn expand_pattern(pattern: &Path) -> Result<Vec<PathBuf>, SmError> {
+    let file_pattern = match pattern.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.contains(['*', '?']) => name,
+        _ => return Ok(vec![pattern.to_path_buf()]),
+    };
+
+    let dir = pattern.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut paths = fs::read_dir(dir)
+        .map_err(|e| SmError::InvalidConfig(format!("failed to read {}: {}", dir.display(), e)))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| wildcard_match(file_pattern, name))
+        })
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match (pattern.first(), name.first()) {
+            (None, None) => true,
+            (Some('*'), _) => {
+                matches(&pattern[1..], name) || (!name.is_empty() && matches(pattern, &name[1..]))
+            }
+            (Some('?'), Some(_)) => matches(&pattern[1..], &name[1..]),
+            (Some(p), Some(n)) if p == n => matches(&pattern[1..], &name[1..]),
+            _ => false,
+        }
+    }
+
+    matches(&pattern, &name)
+}
+
+/// `config.toml` is overridden by `config.local.toml`
+fn local_override_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    path.with_file_name(format!("{}.local.toml", stem))
+}
+
+/// Finds the line and column a key path is defined at by following the table headers and keys of
+/// the file. Inline tables aren't looked into, the position of the key they are assigned to is
+/// returned instead.
+fn find_position(text: &str, path: &[String]) -> Option<(usize, usize)> {
+    let mut table = Vec::new();
+    let mut best = None;
+    let mut in_multiline_string = false;
+
+    for (index, line) in text.lines().enumerate() {
+        let position = (index + 1, line.len() - line.trim_start().len() + 1);
+        let line = line.trim();
+
+        let multiline_quotes = line.matches("\"\"\"").count() + line.matches("'''").count();
+        if in_multiline_string {
+            in_multiline_string = multiline_quotes % 2 == 0;
+            continue;
+        }
+        in_multiline_string = multiline_quotes % 2 == 1;
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let key_path = if let Some(header) = line.strip_prefix('[') {
+            let header = header.trim_start_matches('[');
+            let header = header.split(']').next().unwrap_or_default();
+            table = split_key(header);
+            table.clone()
+        } else if let Some((key, _)) = line.split_once('=') {
+            let mut key_path = table.clone();
+            key_path.extend(split_key(key));
+            key_path
+        } else {
+            continue;
+        };
+
+        if key_path == path {
+            return Some(position);
+        }
+
+        // a table that contains the path is the best guess if the key itself isn't found
+        if path.starts_with(&key_path) && best.is_none_or(|(len, _)| key_path.len() > len) {
+            best = Some((key_path.len(), position));
+        }
+    }
+
+    best.map(|(_, position)| position)
+}
+
+/// Splits a dotted key like `a."b.c"` into its parts
+fn split_key(key: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quote = None;
+
+    for c in key.trim().chars() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('.', None) => parts.push(std::mem::take(&mut part).trim().to_string()),
+            (c, _) => part.push(c),
+        }
+    }
+
+    parts.push(part.trim().to_string());
+    parts
+}
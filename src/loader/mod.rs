@@ -0,0 +1,308 @@
+mod compose;
+mod interpolate;
+mod layers;
+mod procfile;
+mod schema;
+mod systemd;
+mod tasks;
+mod templates;
+mod validate;
+
+use crate::controller::{inherited_env, read_env_files};
+pub use crate::loader::compose::{import_compose, COMPOSE_FILES};
+use crate::loader::interpolate::{interpolate, Expansion};
+use crate::loader::layers::Layers;
+pub use crate::loader::schema::config_schema;
+pub use crate::loader::systemd::{export_systemd, GENERATED_HEADER};
+use crate::loader::tasks::discover_tasks;
+use crate::loader::templates::expand_templates;
+use crate::loader::validate::{check_keys, check_services, global_fields};
+use crate::model::config::{CommandLine, Config, Service};
+use crate::model::SmError;
+use serde::de::DeserializeOwned;
+use serde_path_to_error::Segment;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::{env, slice};
+use toml::value::Table;
+use toml::Value;
+
+/// Reads the config file together with its includes and local overrides and expands the
+/// variables in it. All problems of the config are reported at once.
+pub fn load_config(path: &Path) -> Result<Config, SmError> {
+    let config_dir = config_dir(path)?;
+
+    let layers = Layers::load(path, &config_dir)?;
+
+    let mut problems = Vec::new();
+
+    check_keys(&layers, &mut problems);
+
+    // services that failed to deserialize would cause confusing follow-up problems
+    let mut config = match deserialize_config(&layers, &mut problems) {
+        Some(config) => config,
+        None => return Err(SmError::ConfigProblems(problems)),
+    };
+
+    // like includes, so that it doesn't matter where the service manager is started from
+    config.env_file = config
+        .env_file
+        .iter()
+        .map(|path| config_dir.join(path))
+        .collect();
+
+    expand_templates(&mut config, &layers, &mut problems);
+
+    interpolate_config(&mut config, &config_dir);
+
+    check_services(&config, &layers, &mut problems);
+
+    config.tasks = discover_tasks(&config_dir);
+
+    if problems.is_empty() {
+        Ok(config)
+    } else {
+        // a broken base service is reported by every service that extends it
+        let mut seen = HashSet::new();
+        problems.retain(|problem| seen.insert(problem.clone()));
+
+        Err(SmError::ConfigProblems(problems))
+    }
+}
+
+/// Removes the services that aren't part of the selected profiles. Services without profiles are
+/// always kept, and so are the dependencies of the kept services.
+pub fn select_profiles(config: &mut Config, profiles: &[String]) -> Result<(), SmError> {
+    let known = config
+        .services
+        .values()
+        .flat_map(|service| service.profiles.iter().flatten())
+        .collect::<HashSet<_>>();
+
+    if let Some(unknown) = profiles.iter().find(|profile| !known.contains(profile)) {
+        return Err(SmError::InvalidConfig(format!(
+            "no service has the profile `{}`",
+            unknown
+        )));
+    }
+
+    let mut selected = config
+        .services
+        .iter()
+        .filter(|(_, service)| match &service.profiles {
+            Some(service_profiles) => service_profiles
+                .iter()
+                .any(|profile| profiles.contains(profile)),
+            None => true,
+        })
+        .map(|(name, _)| name.clone())
+        .collect::<HashSet<_>>();
+
+    let mut unvisited = selected.iter().cloned().collect::<Vec<_>>();
+    while let Some(name) = unvisited.pop() {
+        for dependency in config.services[&name].depends_on.iter().flatten() {
+            if selected.insert(dependency.clone()) {
+                unvisited.push(dependency.clone());
+            }
+        }
+    }
+
+    config.services.retain(|name, _| selected.contains(name));
+
+    Ok(())
+}
+
+/// Deserializes the merged config files. The services are deserialized one by one, so that the
+/// problems of all of them are found. Returns `None` if anything failed to deserialize.
+fn deserialize_config(layers: &Layers, problems: &mut Vec<String>) -> Option<Config> {
+    let global_fields = global_fields();
+
+    let (globals, services) = layers
+        .table
+        .clone()
+        .into_iter()
+        .partition::<Table, _>(|(key, _)| global_fields.contains(&key.as_str()));
+
+    let config = deserialize_part::<Config>(Value::Table(globals), &[], layers, problems);
+
+    let services = services
+        .into_iter()
+        // values that aren't tables were already reported as unknown settings
+        .filter(|(_, service)| service.is_table())
+        .map(|(name, service)| {
+            deserialize_part::<Service>(service, slice::from_ref(&name), layers, problems)
+                .map(|service| (name, service))
+        })
+        .collect::<Vec<_>>();
+
+    let mut config = config?;
+    for service in services {
+        let (name, service) = service?;
+        config.services.insert(name, service);
+    }
+
+    Some(config)
+}
+
+/// Deserializes the value at `prefix`. Errors point to the file and line the bad value came from.
+fn deserialize_part<T: DeserializeOwned>(
+    value: Value,
+    prefix: &[String],
+    layers: &Layers,
+    problems: &mut Vec<String>,
+) -> Option<T> {
+    let err = match serde_path_to_error::deserialize(value) {
+        Ok(value) => return Some(value),
+        Err(err) => err,
+    };
+
+    let path = prefix
+        .iter()
+        .cloned()
+        .chain(err.path().iter().map(|segment| match segment {
+            Segment::Map { key } => key.clone(),
+            Segment::Seq { index } => index.to_string(),
+            Segment::Enum { variant } => variant.clone(),
+            Segment::Unknown => "?".to_string(),
+        }))
+        .collect::<Vec<_>>();
+
+    let location = layers
+        .location(&path)
+        .unwrap_or_else(|| layers.sources[0].path.display().to_string());
+
+    let msg = err.into_inner().to_string();
+    // the key is already part of the message, in the form the user wrote it
+    let msg = match msg.find(" for key `") {
+        Some(key) => &msg[..key],
+        None => &msg,
+    };
+
+    if path.is_empty() {
+        problems.push(format!("{}: {}", location, msg));
+    } else {
+        problems.push(format!("{}: `{}`: {}", location, path.join("."), msg));
+    }
+
+    None
+}
+
+/// The directory the config file is in, as an absolute path
+fn config_dir(path: &Path) -> Result<PathBuf, SmError> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let dir = if dir.as_os_str().is_empty() {
+        env::current_dir()?
+    } else {
+        dir.canonicalize()?
+    };
+    Ok(dir)
+}
+
+/// Expands `${...}` in the commands, hooks, workdirs and env values of the services. Variables are
+/// looked up in the built-ins `service.name`, `instance` and `config_dir` first, then in the
+/// environment the service gets and finally in the `[vars]` table. In shell scripts, only the
+/// built-ins and `[vars]` are replaced, everything else is left for the shell, so that the script
+/// sees the same environment as the service and can use its own variables.
+fn interpolate_config(config: &mut Config, config_dir: &Path) {
+    let config_dir = config_dir.display().to_string();
+
+    let global_lookup = |name: &str| match name {
+        "config_dir" => Some(config_dir.clone()),
+        _ => env::var(name).ok(),
+    };
+
+    // vars can use the environment, but not each other
+    let vars = config
+        .vars
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.clone(),
+                interpolate(value, |var| global_lookup(var).into()),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    let global_inherit_env = config.inherit_env.clone().unwrap_or_default();
+    let current_dir = env::current_dir().unwrap_or_default();
+
+    for (name, service) in config.services.iter_mut() {
+        let instance = service.instance;
+        let builtin = |var: &str| match var {
+            "service.name" => Some(name.clone()),
+            "instance" => instance.map(|instance| instance.to_string()),
+            "config_dir" => Some(config_dir.clone()),
+            _ => None,
+        };
+        // the value the service sees if `env` is its environment
+        let value_in = |env: &BTreeMap<String, String>, var: &str| {
+            builtin(var)
+                .or_else(|| env.get(var).cloned())
+                .or_else(|| vars.get(var).cloned())
+                .into()
+        };
+
+        let inherit_env = service.inherit_env.as_ref().unwrap_or(&global_inherit_env);
+        let mut child_env = inherited_env(inherit_env);
+
+        // the env files are relative to the workdir, so it can't use them
+        if let Some(workdir) = &mut service.workdir {
+            let expanded = interpolate(&workdir.to_string_lossy(), |var| value_in(&child_env, var));
+            *workdir = PathBuf::from(expanded);
+        }
+
+        let workdir = service.workdir.as_deref().unwrap_or(&current_dir);
+        let env_files = config
+            .env_file
+            .iter()
+            .cloned()
+            .chain(
+                service
+                    .env_file
+                    .iter()
+                    .flatten()
+                    .map(|path| workdir.join(path)),
+            )
+            .collect::<Vec<_>>();
+        // a broken env file is reported when the service is started
+        child_env.extend(read_env_files(&env_files).unwrap_or_default());
+
+        for value in service.env.iter_mut().flat_map(|env| env.values_mut()) {
+            *value = interpolate(value, |var| value_in(&child_env, var));
+        }
+        for (var, value) in service.env.iter().flatten() {
+            child_env.insert(var.clone(), value.clone());
+        }
+
+        // shell variables and positional parameters look the same, so only the names the config
+        // knows are replaced
+        let shell_lookup = |var: &str| match builtin(var) {
+            Some(value) => Expansion::Value(value),
+            None if child_env.contains_key(var) => Expansion::Keep,
+            None => vars
+                .get(var)
+                .cloned()
+                .map_or(Expansion::Keep, Expansion::Value),
+        };
+
+        match &mut service.command {
+            Some(CommandLine::Shell(script)) => *script = interpolate(script, shell_lookup),
+            Some(CommandLine::Argv(argv)) => {
+                for arg in argv {
+                    *arg = interpolate(arg, |var| value_in(&child_env, var));
+                }
+            }
+            None => {}
+        }
+
+        let hooks = [
+            &mut service.pre_start,
+            &mut service.post_start,
+            &mut service.pre_stop,
+            &mut service.post_stop,
+        ];
+        for hook in hooks.into_iter().flatten().flatten() {
+            *hook = interpolate(hook, shell_lookup);
+        }
+    }
+}
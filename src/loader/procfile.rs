@@ -0,0 +1,97 @@
+use std::env;
+use std::path::Path;
+use toml::value::Table;
+use toml::Value;
+
+/// The port of the first process if `PORT` isn't set, like foreman
+const DEFAULT_BASE_PORT: u16 = 5000;
+
+/// How far apart the ports of the processes are, like foreman
+const PORT_STEP: u16 = 100;
+
+/// Whether the file is a Procfile like `Procfile` or `Procfile.dev` instead of a TOML config
+pub fn is_procfile(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name == "Procfile" || name.starts_with("Procfile."))
+}
+
+/// Turns the processes of a Procfile into service tables. The services run in `dir`, the directory
+/// of the Procfile, load the `.env` file next to it and get a `PORT` following the convention of
+/// foreman: the base port for the first process and 100 more for each following one.
+pub fn procfile_services(dir: &Path, content: &str) -> Result<Table, (usize, String)> {
+    let base_port = env::var("PORT")
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(DEFAULT_BASE_PORT);
+
+    let mut services = Table::new();
+
+    for (index, (name, command)) in parse_procfile(content)?.into_iter().enumerate() {
+        let port = u16::try_from(index)
+            .ok()
+            .and_then(|index| index.checked_mul(PORT_STEP))
+            .and_then(|offset| base_port.checked_add(offset))
+            .ok_or_else(|| {
+                (
+                    0,
+                    "too many processes to give each of them a port".to_string(),
+                )
+            })?;
+
+        let mut env = Table::new();
+        env.insert("PORT".to_string(), Value::String(port.to_string()));
+
+        let mut service = Table::new();
+        service.insert("command".to_string(), Value::String(command));
+        service.insert(
+            "workdir".to_string(),
+            Value::String(dir.display().to_string()),
+        );
+        service.insert(
+            "env_file".to_string(),
+            Value::Array(vec![Value::String(".env".to_string())]),
+        );
+        service.insert("env".to_string(), Value::Table(env));
+
+        services.insert(name, Value::Table(service));
+    }
+
+    Ok(services)
+}
+
+/// Parses the `name: command` lines of a Procfile. Errors contain the line they occurred on.
+fn parse_procfile(content: &str) -> Result<Vec<(String, String)>, (usize, String)> {
+    let mut processes = Vec::<(String, String)>::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, command) = line
+            .split_once(':')
+            .ok_or_else(|| (line_number, "expected `name: command`".to_string()))?;
+
+        let name = name.trim();
+        let is_valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+        if !is_valid_name {
+            return Err((line_number, format!("invalid process name `{}`", name)));
+        }
+
+        if processes.iter().any(|(other, _)| other == name) {
+            return Err((line_number, format!("process `{}` is defined twice", name)));
+        }
+
+        processes.push((name.to_string(), command.trim().to_string()));
+    }
+
+    Ok(processes)
+}
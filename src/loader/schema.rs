@@ -0,0 +1,60 @@
+use crate::model::config::{Config, Service};
+use crate::model::keymap::{Action, KeyList};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{Schema, SchemaObject};
+
+/// The JSON Schema of the config file, for editors to complete and validate it
+pub fn config_schema() -> String {
+    let mut gen = SchemaGenerator::default();
+
+    let key_list = gen.subschema_for::<KeyList>();
+    let include = gen.subschema_for::<Vec<String>>();
+    let import = gen.subschema_for::<String>();
+    let service = gen.subschema_for::<Service>();
+
+    // created last so that it contains the definitions of the schemas above
+    let mut schema = gen.into_root_schema_for::<Config>();
+
+    let object = schema.schema.object();
+
+    // every table that isn't a setting is a service
+    object.additional_properties = Some(Box::new(service));
+
+    let properties = &mut object.properties;
+
+    // the actions are strings in the config, so the derived schema doesn't know them
+    if let Some(Schema::Object(keybindings)) = properties.get_mut("keybindings") {
+        let keybindings = keybindings.object();
+        keybindings.properties = Action::ALL
+            .iter()
+            .map(|action| (action.name().to_string(), key_list.clone()))
+            .collect();
+        keybindings.additional_properties = Some(Box::new(Schema::Bool(false)));
+    }
+
+    // includes and imports are resolved by the loader before the config is deserialized
+    properties.insert(
+        "include".to_string(),
+        with_description(
+            include,
+            "Config files that are merged into this one, relative to it. File names can contain \
+             `*` and `?`. Only allowed in the main config file.",
+        ),
+    );
+    properties.insert(
+        "import".to_string(),
+        with_description(
+            import,
+            "A Procfile whose processes are added as services, relative to this file. The \
+             services can be adjusted in this file. Only allowed in the main config file.",
+        ),
+    );
+
+    serde_json::to_string_pretty(&schema).unwrap_or_default()
+}
+
+fn with_description(schema: Schema, description: &str) -> Schema {
+    let mut schema = SchemaObject::from(schema);
+    schema.metadata().description = Some(description.to_string());
+    Schema::Object(schema)
+}
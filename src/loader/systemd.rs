@@ -0,0 +1,267 @@
+use crate::model::config::{self, CommandLine, Config, InheritEnv, Service, ServiceKind};
+use std::env;
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+/// How long a service has to stop before it is killed, the same as in the terminal UI
+const STOP_TIMEOUT_SECS: u64 = 5;
+
+/// The first line of every exported unit. Files without it are only overwritten with `--force`.
+pub const GENERATED_HEADER: &str = "# Generated by `service-manager export systemd`";
+
+/// A systemd unit file for a service
+pub struct Unit {
+    /// The file name, like `api.service`
+    pub name: String,
+    pub content: String,
+}
+
+/// Creates a systemd user unit for every service of the config. The units behave like the
+/// services in the terminal UI: they run their command with their shell or directly and are asked
+/// to stop with `SIGTERM`, then killed. Everything that can't be expressed is returned as a warning.
+pub fn export_systemd(config: &Config) -> io::Result<(Vec<Unit>, Vec<String>)> {
+    let cwd = env::current_dir()?;
+
+    let mut units = Vec::new();
+    let mut warnings = Vec::new();
+
+    for (name, service) in &config.services {
+        let inherit_env = service
+            .inherit_env
+            .as_ref()
+            .or(config.inherit_env.as_ref())
+            .cloned()
+            .unwrap_or_default();
+
+        let content = unit_file(name, service, config, &inherit_env, &cwd);
+
+        if service.schedule.is_some() || service.every.is_some() {
+            warnings.push(format!(
+                "{}: the schedule isn't exported, the unit has to be started by a timer",
+                name
+            ));
+        }
+
+        if let InheritEnv::All(false) = inherit_env {
+            warnings.push(format!(
+                "{}: systemd always passes the environment of the user manager, \
+                 `inherit_env = false` can't be exported",
+                name
+            ));
+        }
+
+        units.push(Unit {
+            name: format!("{}.service", escape_unit_name(name)),
+            content,
+        });
+    }
+
+    Ok((units, warnings))
+}
+
+fn unit_file(
+    name: &str,
+    service: &Service,
+    config: &Config,
+    inherit_env: &InheritEnv,
+    cwd: &Path,
+) -> String {
+    let workdir = cwd.join(service.workdir.as_deref().unwrap_or(cwd));
+    let shell = config::shell_args(service.shell.as_deref().or(config.shell.as_deref()));
+
+    // the same order as when the service is started, later files override earlier ones
+    let env_files = config.env_file.iter().map(|path| cwd.join(path)).chain(
+        service
+            .env_file
+            .iter()
+            .flatten()
+            .map(|path| workdir.join(path)),
+    );
+
+    let mut unit = String::new();
+
+    let _ = writeln!(unit, "{}", GENERATED_HEADER);
+    let _ = writeln!(unit, "[Unit]");
+    let _ = writeln!(unit, "Description={}", escape_specifiers(name));
+
+    let dependencies = service
+        .depends_on
+        .iter()
+        .flatten()
+        .map(|name| format!("{}.service", escape_unit_name(name)))
+        .collect::<Vec<_>>();
+    if !dependencies.is_empty() {
+        let _ = writeln!(unit, "Requires={}", dependencies.join(" "));
+        let _ = writeln!(unit, "After={}", dependencies.join(" "));
+    }
+
+    let _ = writeln!(unit);
+    let _ = writeln!(unit, "[Service]");
+
+    // oneshot services count as started once they completed, which makes `After=` wait for that
+    if service.kind == Some(ServiceKind::Oneshot) {
+        let _ = writeln!(unit, "Type=oneshot");
+        let _ = writeln!(unit, "RemainAfterExit=yes");
+    }
+    let _ = writeln!(
+        unit,
+        "ExecStart={}",
+        match service.command.clone().unwrap_or_default() {
+            CommandLine::Shell(script) => shell_exec(&shell, &script),
+            CommandLine::Argv(mut argv) => {
+                // systemd needs an absolute path, a program without a slash is looked up in $PATH
+                if let Some(program) = argv.first_mut().filter(|program| program.contains('/')) {
+                    *program = workdir
+                        .join(program.trim_start_matches("./"))
+                        .display()
+                        .to_string();
+                }
+                exec(&argv)
+            }
+        }
+    );
+
+    let hooks = [
+        ("ExecStartPre", &service.pre_start),
+        ("ExecStartPost", &service.post_start),
+        ("ExecStop", &service.pre_stop),
+        ("ExecStopPost", &service.post_stop),
+    ];
+    for (key, commands) in hooks {
+        for command in commands.iter().flatten() {
+            let _ = writeln!(unit, "{}={}", key, shell_exec(&shell, command));
+        }
+    }
+
+    let _ = writeln!(
+        unit,
+        "WorkingDirectory={}",
+        escape_specifiers(&workdir.display().to_string())
+    );
+
+    // missing env files are skipped, which `-` does in systemd
+    for path in env_files {
+        let _ = writeln!(
+            unit,
+            "EnvironmentFile=-{}",
+            escape_specifiers(&path.display().to_string())
+        );
+    }
+
+    let mut env = service.env.iter().flatten().collect::<Vec<_>>();
+    env.sort();
+    for (var, value) in env {
+        let _ = writeln!(unit, "Environment={}", quote(&format!("{}={}", var, value)));
+    }
+
+    if let InheritEnv::Only(names) = inherit_env {
+        let _ = writeln!(unit, "PassEnvironment={}", names.join(" "));
+    }
+
+    for (name, limit) in service.limits.iter().flat_map(|limits| limits.iter()) {
+        let key = match name {
+            "cpu_seconds" => "CPU".to_string(),
+            name => name.to_uppercase(),
+        };
+        let value = match limit.value() {
+            Ok(Some(value)) => value.to_string(),
+            _ => "infinity".to_string(),
+        };
+        let _ = writeln!(unit, "Limit{}={}", key, value);
+    }
+
+    if let Some(nice) = service.nice {
+        let _ = writeln!(unit, "Nice={}", nice);
+    }
+
+    if let Some(oom_score_adj) = service.oom_score_adj {
+        let _ = writeln!(unit, "OOMScoreAdjust={}", oom_score_adj);
+    }
+
+    if let Some(memory_max) = &service.memory_max {
+        let value = match memory_max.value() {
+            Ok(Some(value)) => value.to_string(),
+            _ => "infinity".to_string(),
+        };
+        let _ = writeln!(unit, "MemoryMax={}", value);
+    }
+
+    if let Some(cpu_max) = service.cpu_max {
+        let _ = writeln!(unit, "CPUQuota={}%", (cpu_max * 100.0).round());
+    }
+
+    let _ = writeln!(unit, "KillSignal=SIGTERM");
+    let _ = writeln!(unit, "KillMode=control-group");
+    let _ = writeln!(unit, "TimeoutStopSec={}", STOP_TIMEOUT_SECS);
+    let _ = writeln!(unit, "Restart=no");
+    let _ = writeln!(unit);
+    let _ = writeln!(unit, "[Install]");
+    let _ = writeln!(unit, "WantedBy=default.target");
+
+    unit
+}
+
+/// Runs the script with the shell like the terminal UI does
+fn shell_exec(shell: &[String], script: &str) -> String {
+    let mut args = shell.to_vec();
+    args.push(script.to_string());
+    exec(&args)
+}
+
+/// The command line of an `Exec` setting
+fn exec(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            // `$` is only expanded by systemd in commands
+            let arg = arg.replace('$', "$$");
+
+            let is_plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./:=@+,".contains(c));
+            if is_plain {
+                arg
+            } else {
+                quote(&arg)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes a value for systemd, which unescapes C-style escapes inside double quotes
+fn quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            // systemd would expand specifiers like `%h` itself
+            '%' => quoted.push_str("%%"),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+fn escape_specifiers(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+/// Escapes the characters that aren't allowed in unit names like `systemd-escape` does
+fn escape_unit_name(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b':' | b'_' | b'.' | b'-' => {
+                (b as char).to_string()
+            }
+            b => format!("\\x{:02x}", b),
+        })
+        .collect()
+}
@@ -0,0 +1,151 @@
+use crate::model::config::{CommandLine, Service, ServiceKind};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+struct PackageJson {
+    #[serde(default)]
+    scripts: HashMap<String, serde_json::Value>,
+}
+
+/// Finds the `package.json` scripts, `Makefile` targets and `justfile` recipes in `dir`. They are
+/// offered as tasks next to the services, grouped by the file they are defined in.
+pub fn discover_tasks(dir: &Path) -> BTreeMap<String, Service> {
+    let mut tasks = BTreeMap::new();
+
+    let mut add = |file: &str, runner: &str, names: Vec<String>| {
+        for name in names {
+            // names can contain anything, so they don't go through a shell
+            let argv = runner
+                .split_whitespace()
+                .map(str::to_string)
+                .chain([name.clone()])
+                .collect();
+            let task = Service {
+                command: Some(CommandLine::Argv(argv)),
+                workdir: Some(dir.to_path_buf()),
+                group: Some(file.to_string()),
+                kind: Some(ServiceKind::Oneshot),
+                ..Service::default()
+            };
+            tasks.insert(format!("{} {}", runner, name), task);
+        }
+    };
+
+    if let Ok(content) = fs::read_to_string(dir.join("package.json")) {
+        if let Ok(package) = serde_json::from_str::<PackageJson>(&content) {
+            add(
+                "package.json",
+                package_runner(dir),
+                package.scripts.into_keys().collect(),
+            );
+        }
+    }
+
+    for file in ["Makefile", "makefile", "GNUmakefile"] {
+        if let Ok(content) = fs::read_to_string(dir.join(file)) {
+            add(file, "make", make_targets(&content));
+            break;
+        }
+    }
+
+    for file in ["justfile", "Justfile", ".justfile"] {
+        if let Ok(content) = fs::read_to_string(dir.join(file)) {
+            add(file, "just", just_recipes(&content));
+            break;
+        }
+    }
+
+    tasks
+}
+
+/// The package manager whose lock file is in `dir`, npm if there is none
+fn package_runner(dir: &Path) -> &'static str {
+    if dir.join("pnpm-lock.yaml").exists() {
+        "pnpm run"
+    } else if dir.join("yarn.lock").exists() {
+        "yarn run"
+    } else {
+        "npm run"
+    }
+}
+
+/// The explicit targets of a Makefile. Special targets like `.PHONY`, pattern rules and variable
+/// assignments are left out.
+fn make_targets(content: &str) -> Vec<String> {
+    let mut targets = Vec::new();
+
+    for line in content.lines() {
+        if line.starts_with(['\t', ' ', '#', '.']) {
+            continue;
+        }
+
+        let (names, rest) = match line.split_once(':') {
+            Some(split) => split,
+            None => continue,
+        };
+
+        // `a := b` and `a ::= b` are assignments
+        if rest.starts_with('=') || rest.starts_with(":=") || names.contains('=') {
+            continue;
+        }
+
+        for name in names.split_whitespace() {
+            let is_plain = name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c));
+
+            if is_plain && !targets.iter().any(|target| target == name) {
+                targets.push(name.to_string());
+            }
+        }
+    }
+
+    targets
+}
+
+/// The public recipes of a justfile that can be run without arguments
+fn just_recipes(content: &str) -> Vec<String> {
+    let mut recipes = Vec::new();
+
+    for line in content.lines() {
+        if line.starts_with([' ', '\t', '#', '[']) {
+            continue;
+        }
+
+        let (signature, rest) = match line.split_once(':') {
+            Some(split) => split,
+            None => continue,
+        };
+
+        // `a := b` is an assignment
+        if rest.starts_with('=') {
+            continue;
+        }
+
+        let mut parts = signature.split_whitespace();
+        let name = match parts.next() {
+            Some(name) => name.trim_start_matches('@'),
+            None => continue,
+        };
+
+        if name.is_empty()
+            || name.starts_with('_')
+            || ["set", "alias", "export", "import", "mod"].contains(&name)
+        {
+            continue;
+        }
+
+        // parameters without a default value have to be passed, variadic ones starting with `*`
+        // can be empty
+        let needs_arguments = parts.any(|param| !param.contains('=') && !param.starts_with('*'));
+
+        if !needs_arguments {
+            recipes.push(name.to_string());
+        }
+    }
+
+    recipes
+}
@@ -0,0 +1,167 @@
+use crate::loader::layers::Layers;
+use crate::model::config::{Config, Service};
+use std::collections::BTreeMap;
+
+/// Resolves `extends`, removes the templates and expands services with `instances` into
+/// `name-1` to `name-n`, also in the dependencies of other services. Services with problems are
+/// left out, so every remaining service has a command.
+pub fn expand_templates(config: &mut Config, layers: &Layers, problems: &mut Vec<String>) {
+    let names = config.services.keys().cloned().collect::<Vec<_>>();
+
+    let mut resolved = BTreeMap::new();
+    for name in &names {
+        let result = resolve(
+            name,
+            &config.services,
+            &mut resolved,
+            &mut Vec::new(),
+            layers,
+        );
+
+        if let Err(problem) = result {
+            problems.push(problem);
+        }
+    }
+
+    let mut services = BTreeMap::new();
+
+    for (name, service) in resolved {
+        if service.template == Some(true) {
+            continue;
+        }
+
+        if service.command.is_none() {
+            let msg = format!("service `{}` has no command", name);
+            problems.push(layers.problem(&[&name], msg));
+            continue;
+        }
+
+        match service.instances {
+            None => {
+                services.insert(name, service);
+            }
+            Some(0) => {
+                let msg = format!("service `{}` needs at least one instance", name);
+                problems.push(layers.problem(&[&name, "instances"], msg));
+            }
+            Some(instances) => {
+                for instance in 1..=instances {
+                    let mut service = service.clone();
+                    service.instance = Some(instance);
+                    // the instances are shown together, unless the service is in a group already
+                    service.group.get_or_insert_with(|| name.clone());
+                    services.insert(format!("{}-{}", name, instance), service);
+                }
+            }
+        }
+    }
+
+    expand_dependencies(&mut services);
+
+    config.services = services;
+}
+
+/// Replaces dependencies on services with `instances` with all of their instances
+fn expand_dependencies(services: &mut BTreeMap<String, Service>) {
+    let mut instances = BTreeMap::<String, Vec<String>>::new();
+    for (name, service) in services.iter() {
+        if let (Some(_), Some((defined_name, _))) = (service.instance, name.rsplit_once('-')) {
+            instances
+                .entry(defined_name.to_string())
+                .or_default()
+                .push(name.clone());
+        }
+    }
+
+    for service in services.values_mut() {
+        if let Some(depends_on) = &mut service.depends_on {
+            *depends_on = depends_on
+                .drain(..)
+                .flat_map(|name| instances.get(&name).cloned().unwrap_or_else(|| vec![name]))
+                .collect();
+        }
+    }
+}
+
+/// Merges a service with the service it extends, which is resolved first
+fn resolve(
+    name: &str,
+    services: &BTreeMap<String, Service>,
+    resolved: &mut BTreeMap<String, Service>,
+    stack: &mut Vec<String>,
+    layers: &Layers,
+) -> Result<(), String> {
+    if resolved.contains_key(name) {
+        return Ok(());
+    }
+
+    let service = &services[name];
+
+    let base_name = match &service.extends {
+        Some(base_name) => base_name,
+        None => {
+            resolved.insert(name.to_string(), service.clone());
+            return Ok(());
+        }
+    };
+
+    if !services.contains_key(base_name) {
+        let msg = format!("service `{}` extends unknown service `{}`", name, base_name);
+        return Err(layers.problem(&[name, "extends"], msg));
+    }
+
+    if stack.iter().any(|extending| extending == name) {
+        let msg = format!("service `{}` extends itself through `{}`", name, base_name);
+        return Err(layers.problem(&[name, "extends"], msg));
+    }
+
+    stack.push(name.to_string());
+    resolve(base_name, services, resolved, stack, layers)?;
+    stack.pop();
+
+    let service = inherit(service.clone(), &resolved[base_name]);
+    resolved.insert(name.to_string(), service);
+
+    Ok(())
+}
+
+/// Fills the values the service doesn't set from its base. The `env` tables are merged.
+fn inherit(service: Service, base: &Service) -> Service {
+    let env = match (&base.env, service.env) {
+        (Some(base_env), Some(env)) => {
+            let mut merged = base_env.clone();
+            merged.extend(env);
+            Some(merged)
+        }
+        (base_env, env) => env.or_else(|| base_env.clone()),
+    };
+
+    Service {
+        command: service.command.or_else(|| base.command.clone()),
+        shell: service.shell.or_else(|| base.shell.clone()),
+        workdir: service.workdir.or_else(|| base.workdir.clone()),
+        env,
+        env_file: service.env_file.or_else(|| base.env_file.clone()),
+        inherit_env: service.inherit_env.or_else(|| base.inherit_env.clone()),
+        group: service.group.or_else(|| base.group.clone()),
+        profiles: service.profiles.or_else(|| base.profiles.clone()),
+        extends: service.extends,
+        // being a template isn't inherited, that's what extending it is for
+        template: service.template,
+        instances: service.instances.or(base.instances),
+        kind: service.kind.or(base.kind),
+        depends_on: service.depends_on.or_else(|| base.depends_on.clone()),
+        schedule: service.schedule.or_else(|| base.schedule.clone()),
+        every: service.every.or_else(|| base.every.clone()),
+        pre_start: service.pre_start.or_else(|| base.pre_start.clone()),
+        post_start: service.post_start.or_else(|| base.post_start.clone()),
+        pre_stop: service.pre_stop.or_else(|| base.pre_stop.clone()),
+        post_stop: service.post_stop.or_else(|| base.post_stop.clone()),
+        limits: service.limits.or_else(|| base.limits.clone()),
+        nice: service.nice.or(base.nice),
+        oom_score_adj: service.oom_score_adj.or(base.oom_score_adj),
+        memory_max: service.memory_max.or_else(|| base.memory_max.clone()),
+        cpu_max: service.cpu_max.or(base.cpu_max),
+        instance: None,
+    }
+}
@@ -0,0 +1,323 @@
+use crate::controller::hard_limit;
+use crate::loader::layers::Layers;
+use crate::model::config::{CommandLine, Config, Limit, Service};
+use crate::model::schedule::Schedule;
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use std::collections::{BTreeMap, HashSet};
+use toml::Value;
+
+/// Reports the keys that aren't fields of the config or of a service, with a suggestion if there
+/// is a similar field
+pub fn check_keys(layers: &Layers, problems: &mut Vec<String>) {
+    let global_fields = global_fields();
+    let service_fields = struct_fields::<Service>();
+
+    for (key, value) in &layers.table {
+        if global_fields.contains(&key.as_str()) {
+            continue;
+        }
+
+        let service = match value {
+            Value::Table(service) => service,
+            _ => {
+                let hint = match did_you_mean(key, &global_fields) {
+                    hint if hint.is_empty() => ", services have to be tables".to_string(),
+                    hint => hint,
+                };
+                let msg = format!("unknown setting `{}`{}", key, hint);
+                problems.push(layers.problem(&[key], msg));
+                continue;
+            }
+        };
+
+        for field in service.keys() {
+            if !service_fields.contains(&field.as_str()) {
+                let msg = format!(
+                    "unknown field `{}` in service `{}`{}",
+                    field,
+                    key,
+                    did_you_mean(field, service_fields)
+                );
+                problems.push(layers.problem(&[key, field], msg));
+            }
+        }
+    }
+}
+
+/// Checks the services for mistakes that are valid TOML but can't work, like empty commands or
+/// two services using the same port
+pub fn check_services(config: &Config, layers: &Layers, problems: &mut Vec<String>) {
+    // services that are expanded from `instances` are reported by the name they are defined as
+    let defined_name = |name: &str, service: &Service| -> String {
+        match (service.instance, name.rsplit_once('-')) {
+            (Some(_), Some((defined_name, _))) => defined_name.to_string(),
+            _ => name.to_string(),
+        }
+    };
+
+    if config
+        .shell
+        .as_ref()
+        .is_some_and(|shell| shell.trim().is_empty())
+    {
+        problems.push(layers.problem(&["shell"], "the shell is empty".to_string()));
+    }
+
+    let mut ports = BTreeMap::<u16, Vec<&str>>::new();
+
+    for (name, service) in &config.services {
+        let defined_name = defined_name(name, service);
+
+        if service.command.as_ref().is_none_or(CommandLine::is_empty) {
+            let msg = format!("the command of service `{}` is empty", name);
+            problems.push(layers.problem(&[&defined_name, "command"], msg));
+        }
+
+        if service
+            .shell
+            .as_ref()
+            .is_some_and(|shell| shell.trim().is_empty())
+        {
+            let msg = format!("the shell of service `{}` is empty", name);
+            problems.push(layers.problem(&[&defined_name, "shell"], msg));
+        }
+
+        if let Some(workdir) = &service.workdir {
+            if !workdir.is_dir() {
+                let msg = format!(
+                    "the workdir `{}` of service `{}` doesn't exist",
+                    workdir.display(),
+                    name
+                );
+                problems.push(layers.problem(&[&defined_name, "workdir"], msg));
+            }
+        }
+
+        if let Err(problem) =
+            Schedule::from_config(service.schedule.as_deref(), service.every.as_deref())
+        {
+            let key = match service.schedule {
+                Some(_) => "schedule",
+                None => "every",
+            };
+            let msg = format!("invalid schedule of service `{}`: {}", name, problem);
+            problems.push(layers.problem(&[&defined_name, key], msg));
+        }
+
+        for (limit_name, limit) in service.limits.iter().flat_map(|limits| limits.iter()) {
+            if let Err(problem) = limit.value() {
+                let msg = format!(
+                    "invalid limit `{}` of service `{}`: {}",
+                    limit_name, name, problem
+                );
+                problems.push(layers.problem(&[&defined_name, "limits", limit_name], msg));
+            } else if let (Ok(value), Some(hard)) = (limit.value(), hard_limit(limit_name)) {
+                // raising the hard limit needs root, so setting it would fail when starting
+                if value.is_none_or(|value| value > hard) {
+                    let msg = format!(
+                        "the limit `{}` of service `{}` is above the hard limit of {}",
+                        limit_name, name, hard
+                    );
+                    problems.push(layers.problem(&[&defined_name, "limits", limit_name], msg));
+                }
+            }
+        }
+
+        if service.nice.is_some_and(|nice| !(-20..=19).contains(&nice)) {
+            let msg = format!(
+                "the nice value of service `{}` has to be between -20 and 19",
+                name
+            );
+            problems.push(layers.problem(&[&defined_name, "nice"], msg));
+        }
+
+        let oom_score_adj = service.oom_score_adj;
+        if oom_score_adj.is_some_and(|adj| !(-1000..=1000).contains(&adj)) {
+            let msg = format!(
+                "the oom_score_adj of service `{}` has to be between -1000 and 1000",
+                name
+            );
+            problems.push(layers.problem(&[&defined_name, "oom_score_adj"], msg));
+        }
+
+        if let Some(Err(problem)) = service.memory_max.as_ref().map(Limit::value) {
+            let msg = format!("invalid memory_max of service `{}`: {}", name, problem);
+            problems.push(layers.problem(&[&defined_name, "memory_max"], msg));
+        }
+
+        // the kernel doesn't accept a quota below 1% of the period
+        if service
+            .cpu_max
+            .is_some_and(|cpu_max| !cpu_max.is_finite() || cpu_max < 0.01)
+        {
+            let msg = format!(
+                "the cpu_max of service `{}` has to be a number of CPUs of at least 0.01",
+                name
+            );
+            problems.push(layers.problem(&[&defined_name, "cpu_max"], msg));
+        }
+
+        for dependency in service.depends_on.iter().flatten() {
+            if !config.services.contains_key(dependency) {
+                let msg = format!(
+                    "service `{}` depends on unknown service `{}`",
+                    name, dependency
+                );
+                problems.push(layers.problem(&[&defined_name, "depends_on"], msg));
+            }
+        }
+
+        for (var, value) in service.env.iter().flatten() {
+            if var == "PORT" || var.ends_with("_PORT") {
+                if let Ok(port) = value.parse() {
+                    ports.entry(port).or_default().push(name);
+                }
+            }
+        }
+    }
+
+    for (port, names) in ports {
+        for (i, name) in names.iter().enumerate() {
+            let service = &config.services[*name];
+
+            // services of different profiles are never loaded together
+            let conflict = names[..i]
+                .iter()
+                .find(|other| share_profile(service, &config.services[**other]));
+
+            if let Some(other) = conflict {
+                let msg = format!(
+                    "service `{}` uses port {} which is already used by `{}`",
+                    name, port, other
+                );
+                problems.push(layers.problem(&[&defined_name(name, service), "env"], msg));
+            }
+        }
+    }
+
+    let mut checked = HashSet::new();
+    for name in config.services.keys() {
+        let cycle = dependency_cycle(name, &config.services, &mut Vec::new(), &mut checked);
+
+        if let Some(cycle) = cycle {
+            let first = cycle[0];
+            let msg = format!(
+                "service `{}` depends on itself: {}",
+                first,
+                cycle.join(" -> ")
+            );
+            let defined_name = defined_name(first, &config.services[first]);
+            problems.push(layers.problem(&[&defined_name, "depends_on"], msg));
+        }
+    }
+}
+
+/// Follows the dependencies of a service until one of them is on the `stack` again. Every
+/// service is only followed once, so a cycle is only found from its first service.
+fn dependency_cycle<'a>(
+    name: &'a str,
+    services: &'a BTreeMap<String, Service>,
+    stack: &mut Vec<&'a str>,
+    checked: &mut HashSet<&'a str>,
+) -> Option<Vec<&'a str>> {
+    if let Some(start) = stack.iter().position(|on_stack| *on_stack == name) {
+        let mut cycle = stack[start..].to_vec();
+        cycle.push(name);
+        return Some(cycle);
+    }
+
+    if !checked.insert(name) {
+        return None;
+    }
+
+    stack.push(name);
+    for dependency in services.get(name)?.depends_on.iter().flatten() {
+        if let Some(cycle) = dependency_cycle(dependency, services, stack, checked) {
+            return Some(cycle);
+        }
+    }
+    stack.pop();
+
+    None
+}
+
+fn share_profile(a: &Service, b: &Service) -> bool {
+    match (&a.profiles, &b.profiles) {
+        (Some(a), Some(b)) => a.iter().any(|profile| b.contains(profile)),
+        _ => true,
+    }
+}
+
+/// The top level keys that aren't services
+pub fn global_fields() -> Vec<&'static str> {
+    struct_fields::<Config>()
+        .iter()
+        .copied()
+        .filter(|field| *field != "services")
+        .collect()
+}
+
+fn did_you_mean(key: &str, fields: &[&str]) -> String {
+    fields
+        .iter()
+        .map(|field| (edit_distance(key, field), field))
+        .filter(|(distance, _)| *distance <= 2.max(key.len() / 3))
+        .min()
+        .map(|(_, field)| format!(", did you mean `{}`?", field))
+        .unwrap_or_default()
+}
+
+/// The Levenshtein distance between the strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            let insertion = current[j] + 1;
+            let deletion = previous[j + 1] + 1;
+            current.push(substitution.min(insertion).min(deletion));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// The fields of a struct as serde sees them. They are taken from the `Deserialize` impl so that
+/// the known keys can't get out of sync with the config types.
+fn struct_fields<T: DeserializeOwned>() -> &'static [&'static str] {
+    struct FieldsDeserializer<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for FieldsDeserializer<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("expected a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("only the fields are needed"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+            ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldsDeserializer(&mut fields));
+    fields
+}
@@ -1,4 +1,6 @@
+mod args;
 mod controller;
+mod loader;
 mod model;
 mod view;
 
@@ -7,42 +9,74 @@ use crossterm::execute;
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::StdoutLock;
-use std::{env, fs, io};
+use std::path::{Path, PathBuf};
+use std::{env, io};
 use tracing::info;
 use tui::backend::CrosstermBackend;
 use tui::Terminal;
 
+use crate::args::{Args, Command, USAGE};
+use crate::loader::COMPOSE_FILES;
 use crate::model::config::Config;
-use crate::model::App;
+use crate::model::keymap::Keymap;
+use crate::model::{App, SmError};
 
 fn main() {
-    setup_logging();
+    let args = Args::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("error: {}\n{}", e, USAGE);
+        std::process::exit(1);
+    });
 
-    let file_path = env::args()
-        .nth(1)
-        .or_else(|| env::var("SERVICE_MANAGER_CONFIG_PATH").ok())
-        .or_else(|| Some("config.toml".to_string()))
-        .unwrap_or_else(|| {
-            eprintln!(
-                "error: config file not found
-usage: <filepath>
-or use the environment variable SERVICE_MANAGER_CONFIG_PATH"
-            );
+    if let Command::Schema = args.command {
+        println!("{}", loader::config_schema());
+        return;
+    }
+
+    if let Command::ImportCompose = args.command {
+        if let Err(e) = import_compose(args.path, args.out) {
+            eprintln!("error: {}", e);
             std::process::exit(1);
-        });
+        }
+        return;
+    }
 
-    let config_file = fs::read(file_path).unwrap_or_else(|e| {
-        eprintln!("error: failed to read file: {}", e);
+    let file_path = args
+        .path
+        .or_else(|| env::var("SERVICE_MANAGER_CONFIG_PATH").ok())
+        .unwrap_or_else(|| "config.toml".to_string());
+
+    let mut config = loader::load_config(Path::new(&file_path)).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
         std::process::exit(1);
     });
 
-    let config = toml::from_slice::<Config>(&config_file).unwrap_or_else(|e| {
-        eprintln!("error: invalid config file: {}", e);
+    loader::select_profiles(&mut config, &args.profiles).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+
+    let keymap = Keymap::new(&config.keybindings).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
         std::process::exit(1);
     });
 
+    if let Command::Check = args.command {
+        println!("{} is valid, {} services", file_path, config.services.len());
+        return;
+    }
+
+    if let Command::ExportSystemd = args.command {
+        if let Err(e) = export_systemd(&config, args.out, args.force) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    setup_logging();
+
     let stdout = io::stdout();
     let stdout = stdout.lock();
 
@@ -52,7 +86,7 @@ or use the environment variable SERVICE_MANAGER_CONFIG_PATH"
     });
 
     // create app and run it
-    let app = App::new(config);
+    let app = App::new(config, keymap);
 
     if let Ok(app) = app {
         let res = controller::run_app(&mut terminal, app);
@@ -81,6 +115,83 @@ or use the environment variable SERVICE_MANAGER_CONFIG_PATH"
     }
 }
 
+fn import_compose(path: Option<String>, out: Option<String>) -> Result<(), SmError> {
+    let path = path
+        .map(PathBuf::from)
+        .or_else(|| {
+            COMPOSE_FILES
+                .iter()
+                .map(PathBuf::from)
+                .find(|path| path.exists())
+        })
+        .ok_or_else(|| SmError::InvalidConfig("no docker-compose file found".to_string()))?;
+
+    let (config, warnings) = loader::import_compose(&path)?;
+
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+
+    match out {
+        Some(out) => fs::write(out, config)?,
+        None => print!("{}", config),
+    }
+
+    Ok(())
+}
+
+fn export_systemd(config: &Config, out: Option<String>, force: bool) -> Result<(), SmError> {
+    let out = match out {
+        Some(out) => PathBuf::from(out),
+        None => env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .ok_or_else(|| SmError::InvalidConfig("no directory to export to".to_string()))?
+            .join("systemd/user"),
+    };
+
+    let (units, warnings) = loader::export_systemd(config)?;
+
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+
+    // a service can be named like a unit the user wrote
+    let foreign = units
+        .iter()
+        .map(|unit| out.join(&unit.name))
+        .filter(|path| match fs::read_to_string(path) {
+            Ok(content) => !content.starts_with(loader::GENERATED_HEADER),
+            Err(err) => err.kind() != io::ErrorKind::NotFound,
+        })
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>();
+
+    if !foreign.is_empty() && !force {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "not overwriting units that weren't exported by service-manager: {}, \
+                 use --force to overwrite them",
+                foreign.join(", ")
+            ),
+        )
+        .into());
+    }
+
+    fs::create_dir_all(&out)?;
+
+    for unit in units {
+        let path = out.join(&unit.name);
+        fs::write(&path, unit.content)?;
+        println!("wrote {}", path.display());
+    }
+
+    println!("run `systemctl --user daemon-reload` to load the units");
+
+    Ok(())
+}
+
 fn setup_logging() {
     let log_file = File::create("service-manager.log").unwrap();
 
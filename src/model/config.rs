@@ -0,0 +1,277 @@
+use crate::model::keymap::KeyList;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Config {
+    /// Maps actions to the keys that trigger them, like `kill = ["x", "ctrl-k"]`
+    #[serde(default)]
+    pub keybindings: HashMap<String, KeyList>,
+    /// `.env` files relative to the config file that are loaded for every service, before the
+    /// ones of the service. Missing files are skipped.
+    #[serde(default)]
+    pub env_file: Vec<PathBuf>,
+    /// The default for `inherit_env` of the services
+    pub inherit_env: Option<InheritEnv>,
+    /// The default for `shell` of the services
+    pub shell: Option<String>,
+    /// Variables that can be used as `${name}` in the services
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    /// Every other table of the config files is a service
+    #[serde(default)]
+    #[schemars(skip)]
+    pub services: BTreeMap<String, Service>,
+    /// Scripts and targets found next to the config, they aren't part of the config file
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub tasks: BTreeMap<String, Service>,
+}
+
+/// Which variables of the environment of the service manager are passed on to a service
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum InheritEnv {
+    /// Either the whole environment or nothing
+    All(bool),
+    /// Only the listed variables
+    Only(Vec<String>),
+}
+
+impl Default for InheritEnv {
+    fn default() -> Self {
+        Self::All(true)
+    }
+}
+
+/// What a service runs
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum CommandLine {
+    /// A script that is run with the shell
+    Shell(String),
+    /// A program and its arguments, which is run directly without a shell
+    Argv(Vec<String>),
+}
+
+impl Default for CommandLine {
+    fn default() -> Self {
+        Self::Shell(String::new())
+    }
+}
+
+impl CommandLine {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            CommandLine::Shell(script) => script.trim().is_empty(),
+            CommandLine::Argv(argv) => argv.first().is_none_or(|program| program.is_empty()),
+        }
+    }
+}
+
+impl Display for CommandLine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandLine::Shell(script) => f.write_str(script),
+            CommandLine::Argv(argv) => write!(f, "{:?}", argv),
+        }
+    }
+}
+
+/// The program and the arguments a script is run with, the script is appended to them. `-c` is
+/// added unless the last argument already has it, like in `bash -lc`, so that `zsh` and
+/// `bash -l` work as well. Defaults to `sh -c`.
+pub fn shell_args(shell: Option<&str>) -> Vec<String> {
+    let mut args = shell
+        .unwrap_or("sh")
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    let has_c = args.iter().skip(1).last().is_some_and(|arg| {
+        arg.strip_prefix('-')
+            .is_some_and(|flags| !flags.starts_with('-') && flags.contains('c'))
+    });
+    if !has_c {
+        args.push("-c".to_string());
+    }
+
+    args
+}
+
+/// Resource limits of a service, each is set as both the soft and the hard limit. Only root can go
+/// above the hard limits of the service manager.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// The number of files that can be open at once
+    pub nofile: Option<Limit>,
+    /// The number of processes the user can have
+    pub nproc: Option<Limit>,
+    /// The size of the virtual memory
+    #[serde(rename = "as")]
+    pub address_space: Option<Limit>,
+    /// The size of the data segment
+    pub data: Option<Limit>,
+    /// The size of the stack
+    pub stack: Option<Limit>,
+    /// The size of files that can be written
+    pub fsize: Option<Limit>,
+    /// The size of core dumps, `0` disables them
+    pub core: Option<Limit>,
+    /// The size of the memory that can be locked
+    pub memlock: Option<Limit>,
+    /// The CPU time in seconds, the service is killed when it used it up
+    pub cpu_seconds: Option<Limit>,
+}
+
+/// A number, a size like `512K`, `2G` or `unlimited`
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Limit {
+    Number(u64),
+    Text(String),
+}
+
+impl Limits {
+    /// The limits that are set, with their name in the config
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &Limit)> {
+        [
+            ("nofile", &self.nofile),
+            ("nproc", &self.nproc),
+            ("as", &self.address_space),
+            ("data", &self.data),
+            ("stack", &self.stack),
+            ("fsize", &self.fsize),
+            ("core", &self.core),
+            ("memlock", &self.memlock),
+            ("cpu_seconds", &self.cpu_seconds),
+        ]
+        .into_iter()
+        .filter_map(|(name, limit)| Some((name, limit.as_ref()?)))
+    }
+}
+
+impl Limit {
+    /// The value of the limit, `None` if it is unlimited. Sizes use powers of 1024.
+    pub fn value(&self) -> Result<Option<u64>, String> {
+        let text = match self {
+            Limit::Number(number) => return Ok(Some(*number)),
+            Limit::Text(text) => text.trim(),
+        };
+
+        if text == "unlimited" || text == "infinity" {
+            return Ok(None);
+        }
+
+        let invalid = || {
+            format!(
+                "expected a number, a size like `2G` or `unlimited`, found `{}`",
+                text
+            )
+        };
+
+        let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+            Some(index) => text.split_at(index),
+            None => (text, ""),
+        };
+
+        let factor = match unit.to_uppercase().as_str() {
+            "" => 1,
+            "K" => 1 << 10,
+            "M" => 1 << 20,
+            "G" => 1 << 30,
+            "T" => 1 << 40,
+            _ => return Err(invalid()),
+        };
+
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(factor))
+            .map(Some)
+            .ok_or_else(invalid)
+    }
+}
+
+/// Whether a service is expected to keep running or to exit
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceKind {
+    /// A service that runs until it is stopped
+    #[default]
+    Simple,
+    /// A service that does its work and exits, like a migration. Exiting with 0 means that it
+    /// completed.
+    Oneshot,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct Service {
+    /// A script that is run with the shell, or a list of a program and its arguments that is run
+    /// without a shell. Can only be left out if the service extends another service.
+    pub command: Option<CommandLine>,
+    /// The shell that runs the command and the hooks, like `bash -l` or `zsh`. `-c` is added if
+    /// it isn't there. Defaults to `sh -c`.
+    pub shell: Option<String>,
+    /// The directory the command is run in, defaults to the current directory
+    pub workdir: Option<PathBuf>,
+    /// Variables that are set for the command, they override the ones from the env files
+    pub env: Option<HashMap<String, String>>,
+    /// `.env` files relative to the workdir, missing files are skipped
+    pub env_file: Option<Vec<PathBuf>>,
+    /// Whether the service gets the environment of the service manager, either `true`, `false`
+    /// or a list of variable names
+    pub inherit_env: Option<InheritEnv>,
+    /// Services of a group are shown together and can be started and stopped at once
+    pub group: Option<String>,
+    /// The service is only loaded if one of its profiles is selected with `--profile`
+    pub profiles: Option<Vec<String>>,
+    /// Another service whose values are used for the ones this service doesn't set
+    pub extends: Option<String>,
+    /// Templates are only used to be extended and aren't loaded themselves
+    pub template: Option<bool>,
+    /// Runs the service several times as `name-1` to `name-n`
+    pub instances: Option<u32>,
+    /// `simple` for services that keep running, `oneshot` for services that are expected to exit
+    #[serde(rename = "type")]
+    pub kind: Option<ServiceKind>,
+    /// Services that are started before this one. It waits until they are running, or until
+    /// they completed if they are oneshot services.
+    pub depends_on: Option<Vec<String>>,
+    /// Runs the service periodically while the service manager is open, a cron expression in
+    /// local time like `*/15 * * * *`
+    pub schedule: Option<String>,
+    /// Runs the service periodically while the service manager is open, like `10m` or `1h30m`
+    pub every: Option<String>,
+    /// Commands that are run before the service is started. If one of them fails, the service
+    /// isn't started.
+    pub pre_start: Option<Vec<String>>,
+    /// Commands that are run after the service has been started
+    pub post_start: Option<Vec<String>>,
+    /// Commands that are run before the service is asked to stop
+    pub pre_stop: Option<Vec<String>>,
+    /// Commands that are run after the service exited, also if it failed
+    pub post_stop: Option<Vec<String>>,
+    /// Resource limits like `{ nofile = 4096, as = "2G" }`, so that a runaway service fails
+    /// instead of taking down the system
+    pub limits: Option<Limits>,
+    /// The niceness the service runs with, from -20 to 19. Only root can go below 0.
+    pub nice: Option<i32>,
+    /// How likely the service is killed when the system runs out of memory, from -1000 to 1000.
+    /// Only root can go below 0.
+    pub oom_score_adj: Option<i32>,
+    /// The memory the service can use at most, like `512M`. Only enforced when the service
+    /// manager runs in a delegated cgroup with the memory controller.
+    pub memory_max: Option<Limit>,
+    /// The number of CPUs the service can use at most, like `0.5`, at least `0.01`. Only
+    /// enforced when the service manager runs in a delegated cgroup with the cpu controller.
+    pub cpu_max: Option<f64>,
+    /// The number of this instance, available as `${instance}`
+    #[serde(skip)]
+    pub instance: Option<u32>,
+}
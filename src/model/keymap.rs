@@ -0,0 +1,392 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use crate::model::SmError;
+
+/// Something the user can do by pressing a key
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Quits the table or leaves the current view
+    Quit,
+    Back,
+    Select,
+    Up,
+    Down,
+    Top,
+    Bottom,
+    HalfPageUp,
+    HalfPageDown,
+    Left,
+    Right,
+    Run,
+    Kill,
+    Restart,
+    RunAll,
+    KillAll,
+    Details,
+    PreviousRun,
+    NextRun,
+    Mark,
+    Split,
+    ToggleLayout,
+    NextPane,
+    Merged,
+    ToggleFilter,
+    /// Switches the table between the services and the discovered tasks
+    Tasks,
+}
+
+impl Action {
+    pub const ALL: [Action; 26] = [
+        Action::Quit,
+        Action::Back,
+        Action::Select,
+        Action::Up,
+        Action::Down,
+        Action::Top,
+        Action::Bottom,
+        Action::HalfPageUp,
+        Action::HalfPageDown,
+        Action::Left,
+        Action::Right,
+        Action::Run,
+        Action::Kill,
+        Action::Restart,
+        Action::RunAll,
+        Action::KillAll,
+        Action::Details,
+        Action::PreviousRun,
+        Action::NextRun,
+        Action::Mark,
+        Action::Split,
+        Action::ToggleLayout,
+        Action::NextPane,
+        Action::Merged,
+        Action::ToggleFilter,
+        Action::Tasks,
+    ];
+
+    /// The name of the action in the `[keybindings]` section of the config
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Back => "back",
+            Action::Select => "select",
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Top => "top",
+            Action::Bottom => "bottom",
+            Action::HalfPageUp => "half-page-up",
+            Action::HalfPageDown => "half-page-down",
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::Run => "run",
+            Action::Kill => "kill",
+            Action::Restart => "restart",
+            Action::RunAll => "run-all",
+            Action::KillAll => "kill-all",
+            Action::Details => "details",
+            Action::PreviousRun => "previous-run",
+            Action::NextRun => "next-run",
+            Action::Mark => "mark",
+            Action::Split => "split",
+            Action::ToggleLayout => "toggle-layout",
+            Action::NextPane => "next-pane",
+            Action::Merged => "merged",
+            Action::ToggleFilter => "toggle-filter",
+            Action::Tasks => "tasks",
+        }
+    }
+
+    fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Action::Quit => &["q"],
+            Action::Back => &["esc"],
+            Action::Select => &["enter"],
+            Action::Up => &["up", "k"],
+            Action::Down => &["down", "j"],
+            Action::Top => &["g g", "home"],
+            Action::Bottom => &["G", "end"],
+            Action::HalfPageUp => &["ctrl-u"],
+            Action::HalfPageDown => &["ctrl-d"],
+            Action::Left => &["left"],
+            Action::Right => &["right"],
+            Action::Run => &["r"],
+            Action::Kill => &["x"],
+            Action::Restart => &["R"],
+            Action::RunAll => &["ctrl-r"],
+            Action::KillAll => &["ctrl-x"],
+            Action::Details => &["d"],
+            Action::PreviousRun => &["["],
+            Action::NextRun => &["]"],
+            Action::Mark => &["m"],
+            Action::Split => &["s"],
+            Action::ToggleLayout => &["l"],
+            Action::NextPane => &["tab"],
+            Action::Merged => &["a"],
+            Action::ToggleFilter => &["space"],
+            Action::Tasks => &["t"],
+        }
+    }
+}
+
+/// The keys bound to an action in the config, either a single key sequence or a list of them.
+/// The keys of a sequence are separated by spaces, like `g g`.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// A key together with the modifiers that have to be held, like `ctrl-d`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+/// Keys that have to be pressed one after another
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeySequence(pub Vec<KeyBinding>);
+
+/// The result of looking up the keys that have been pressed so far
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    Action(Action),
+    /// The keys are the start of a longer sequence, so more keys have to be waited for
+    Prefix,
+    None,
+}
+
+#[derive(Debug)]
+pub struct Keymap {
+    actions: HashMap<KeySequence, Action>,
+    /// The keys of every action in the order they were configured, used for the help footer
+    keys: HashMap<Action, Vec<KeySequence>>,
+}
+
+impl Keymap {
+    /// Creates the keymap from the `[keybindings]` section of the config. Actions that aren't
+    /// configured keep their default keys.
+    pub fn new(config: &HashMap<String, KeyList>) -> Result<Keymap, SmError> {
+        if let Some(unknown) = config
+            .keys()
+            .find(|name| !Action::ALL.iter().any(|action| action.name() == *name))
+        {
+            return Err(SmError::InvalidConfig(format!(
+                "unknown action `{}` in keybindings",
+                unknown
+            )));
+        }
+
+        let mut keymap = Keymap {
+            actions: HashMap::new(),
+            keys: HashMap::new(),
+        };
+
+        for action in Action::ALL {
+            let keys = match config.get(action.name()) {
+                Some(KeyList::One(key)) => vec![key.as_str()],
+                Some(KeyList::Many(keys)) => keys.iter().map(String::as_str).collect(),
+                None => action.default_keys().to_vec(),
+            };
+
+            for key in keys {
+                let sequence = key.parse::<KeySequence>()?;
+
+                // the shorter sequence would always win, so the longer one could never be pressed
+                let prefix_conflict = keymap.actions.iter().find(|(other, _)| {
+                    other.0.len() != sequence.0.len()
+                        && (other.0.starts_with(&sequence.0) || sequence.0.starts_with(&other.0))
+                });
+                if let Some((other_sequence, other)) = prefix_conflict {
+                    return Err(SmError::InvalidConfig(format!(
+                        "key `{}` of `{}` conflicts with key `{}` of `{}`, one starts with the other",
+                        key,
+                        action.name(),
+                        other_sequence
+                            .0
+                            .iter()
+                            .map(KeyBinding::to_string)
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        other.name()
+                    )));
+                }
+
+                if let Some(other) = keymap.actions.insert(sequence.clone(), action) {
+                    return Err(SmError::InvalidConfig(format!(
+                        "key `{}` is bound to both `{}` and `{}`",
+                        key,
+                        other.name(),
+                        action.name()
+                    )));
+                }
+
+                keymap.keys.entry(action).or_default().push(sequence);
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    pub fn lookup(&self, keys: &[KeyBinding]) -> Lookup {
+        if let Some(action) = self.actions.get(&KeySequence(keys.to_vec())) {
+            return Lookup::Action(*action);
+        }
+
+        let is_prefix = self
+            .actions
+            .keys()
+            .any(|sequence| sequence.0.len() > keys.len() && sequence.0.starts_with(keys));
+
+        if is_prefix {
+            Lookup::Prefix
+        } else {
+            Lookup::None
+        }
+    }
+
+    /// The keys that are shown in the help footer for an action
+    pub fn key(&self, action: Action) -> Option<&KeySequence> {
+        self.keys.get(&action).and_then(|keys| keys.first())
+    }
+}
+
+impl From<KeyEvent> for KeyBinding {
+    fn from(key: KeyEvent) -> Self {
+        let mut binding = KeyBinding {
+            code: key.code,
+            modifiers: key.modifiers,
+        };
+
+        // the case of the char already tells whether shift was pressed
+        if let KeyCode::Char(_) = binding.code {
+            binding.modifiers.remove(KeyModifiers::SHIFT);
+        }
+
+        binding
+    }
+}
+
+impl std::str::FromStr for KeySequence {
+    type Err = SmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keys = s
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<KeyBinding>, _>>()?;
+
+        if keys.is_empty() {
+            return Err(SmError::InvalidConfig(format!("invalid key `{}`", s)));
+        }
+
+        Ok(KeySequence(keys))
+    }
+}
+
+impl std::str::FromStr for KeyBinding {
+    type Err = SmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SmError::InvalidConfig(format!("invalid key `{}`", s));
+
+        let mut modifiers = KeyModifiers::empty();
+        let mut rest = s;
+
+        // `-` on its own is a valid key, so only split off modifiers if something follows them
+        while let Some((modifier, key)) = rest.split_once('-').filter(|(_, key)| !key.is_empty()) {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(invalid()),
+            };
+            rest = key;
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_lowercase().as_str() {
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "enter" => KeyCode::Enter,
+                "esc" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "space" => KeyCode::Char(' '),
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                f => match f.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n) => KeyCode::F(n),
+                    None => return Err(invalid()),
+                },
+            },
+        };
+
+        if let KeyCode::Char(_) = code {
+            // shift is already part of the char
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+
+        Ok(KeyBinding { code, modifiers })
+    }
+}
+
+impl Display for KeySequence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for key in &self.0 {
+            Display::fmt(key, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for KeyBinding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            f.write_str("ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            f.write_str("alt-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            f.write_str("shift-")?;
+        }
+
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            KeyCode::Up => f.write_str("up"),
+            KeyCode::Down => f.write_str("down"),
+            KeyCode::Left => f.write_str("left"),
+            KeyCode::Right => f.write_str("right"),
+            KeyCode::Enter => f.write_str("enter"),
+            KeyCode::Esc => f.write_str("esc"),
+            KeyCode::Tab => f.write_str("tab"),
+            KeyCode::BackTab => f.write_str("backtab"),
+            KeyCode::Backspace => f.write_str("backspace"),
+            KeyCode::Delete => f.write_str("delete"),
+            KeyCode::Insert => f.write_str("insert"),
+            KeyCode::Home => f.write_str("home"),
+            KeyCode::End => f.write_str("end"),
+            KeyCode::PageUp => f.write_str("pageup"),
+            KeyCode::PageDown => f.write_str("pagedown"),
+            KeyCode::Null => f.write_str("null"),
+        }
+    }
+}
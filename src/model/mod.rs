@@ -0,0 +1,275 @@
+pub mod config;
+pub mod keymap;
+pub mod schedule;
+
+use crate::controller::{Cgroups, StdioSendBuf};
+use crate::model::config::{CommandLine, InheritEnv, Limit, Limits, ServiceKind};
+use crate::model::keymap::{Action, Keymap};
+use crate::model::schedule::Schedule;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+use tui::layout::Rect;
+use tui::widgets::TableState;
+
+pub use error::{SmError, SmResult};
+
+#[derive(Debug)]
+pub struct App {
+    pub table: AppState,
+    pub selected: Option<usize>,
+    /// How many lines the full view of the selected service is scrolled up from the bottom
+    pub scroll: usize,
+    /// The index of the run shown in the full view, or `None` to show the current run
+    pub viewed_run: Option<usize>,
+    /// The details of the selected service, shown above its output in the full view
+    pub details: Option<Details>,
+    pub split: Option<SplitView>,
+    pub merged: Option<MergedView>,
+    pub thread_terminates: HashMap<usize, mpsc::Sender<()>>,
+    /// The worker thread of the last run of every service, which are waited for when quitting
+    pub workers: HashMap<usize, JoinHandle<()>>,
+    /// Services that have been killed and are started again as soon as they stopped
+    pub pending_restarts: HashSet<usize>,
+    pub keymap: Keymap,
+    pub hitboxes: Hitboxes,
+    /// The cgroup the services are put in, `None` if cgroups can't be used
+    pub cgroups: Option<Cgroups>,
+}
+
+/// The areas of the last drawn frame that can be clicked
+#[derive(Debug, Default)]
+pub struct Hitboxes {
+    /// Rows of the table and the index of their service
+    pub rows: Vec<(Rect, usize)>,
+    /// Panes of the split view and their index
+    pub panes: Vec<(Rect, usize)>,
+    /// The hints in the help footer and the action they trigger
+    pub hints: Vec<(Rect, Action)>,
+}
+
+#[derive(Debug)]
+pub struct AppState {
+    pub table_state: TableState,
+    /// The first service that is visible in the table
+    pub table_offset: usize,
+    pub services: Vec<Service>,
+    /// Groups whose services are hidden in the table
+    pub collapsed: HashSet<String>,
+    /// Whether the table shows the services or the tasks
+    pub tab: Tab,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Tab {
+    Services,
+    /// Scripts and targets that were found next to the config
+    Tasks,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableRow {
+    /// The header of a group, which can be collapsed
+    Group(String),
+    Service(usize),
+}
+
+/// Several services shown next to each other, each pane scrolling on its own
+#[derive(Debug)]
+pub struct SplitView {
+    pub panes: Vec<Pane>,
+    pub focused: usize,
+    pub layout: SplitLayout,
+}
+
+#[derive(Debug)]
+pub struct Pane {
+    pub service: usize,
+    /// How many lines the pane is scrolled up from the bottom. 0 means it follows the output
+    pub scroll: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SplitLayout {
+    /// Panes are placed next to each other
+    Tiled,
+    /// Panes are placed on top of each other
+    Stacked,
+}
+
+/// The output of all services interleaved in the order it arrived
+#[derive(Debug, Default)]
+pub struct MergedView {
+    /// Services whose output is hidden from the merged view
+    pub excluded: HashSet<usize>,
+    /// The service in the filter bar that is toggled next
+    pub cursor: usize,
+}
+
+#[derive(Debug)]
+pub struct Service {
+    pub command: CommandLine,
+    /// The program and arguments the command and the hooks are run with if they are scripts
+    pub shell: Vec<String>,
+    pub name: String,
+    pub workdir: PathBuf,
+    pub env: HashMap<String, String>,
+    /// `.env` files that are loaded in order before `env` is applied
+    pub env_files: Vec<PathBuf>,
+    pub inherit_env: InheritEnv,
+    pub status: Arc<Mutex<ServiceStatus>>,
+    /// The output of the last runs, the current run is the last one
+    pub runs: VecDeque<Run>,
+    pub stdout: StdIoStream,
+    /// Marked services are opened together in the split view
+    pub marked: bool,
+    pub group: Option<String>,
+    /// Tasks are shown in their own tab and are usually run once instead of kept running
+    pub task: bool,
+    pub kind: ServiceKind,
+    /// The names of the services that have to be running or completed before this one starts
+    pub depends_on: Vec<String>,
+    pub schedule: Option<Schedule>,
+    /// When the service is started by its schedule the next time
+    pub next_run: Option<SystemTime>,
+    pub hooks: Hooks,
+    pub limits: Limits,
+    pub nice: Option<i32>,
+    pub oom_score_adj: Option<i32>,
+    pub memory_max: Option<Limit>,
+    /// The number of CPUs the service can use at most
+    pub cpu_max: Option<f64>,
+    /// The cgroup of the current run, if it runs in one
+    pub cgroup: Option<PathBuf>,
+}
+
+/// Commands that are run around the command of a service, in its workdir and environment
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    pub pre_start: Vec<String>,
+    pub post_start: Vec<String>,
+    pub pre_stop: Vec<String>,
+    pub post_stop: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Details {
+    /// The environment the service is started with, or why it couldn't be loaded
+    pub env: Result<BTreeMap<String, String>, String>,
+    /// What the current run used, if it runs in a cgroup
+    pub usage: Option<Usage>,
+}
+
+/// The resources used by the processes in the cgroup of a service, as far as they are accounted
+#[derive(Debug, Clone, Default)]
+pub struct Usage {
+    /// The memory in bytes
+    pub memory: Option<u64>,
+    /// The most memory in bytes that was used at once
+    pub memory_peak: Option<u64>,
+    pub cpu: Option<Duration>,
+}
+
+/// One start of a service
+#[derive(Debug)]
+pub struct Run {
+    pub started: SystemTime,
+    /// When the run ended and the status it ended with
+    pub exit: Option<(SystemTime, ServiceStatus)>,
+    pub output: Output,
+}
+
+/// The stdout and stderr of a service, split into lines
+#[derive(Debug, Default)]
+pub struct Output {
+    pub lines: VecDeque<OutputLine>,
+    /// The last line, if it hasn't been terminated by a newline yet
+    pub partial: Option<OutputLine>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputLine {
+    /// When the start of the line was read from the child
+    pub time: SystemTime,
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub struct StdIoStream {
+    pub recv: mpsc::Receiver<StdioSendBuf>,
+    pub send: mpsc::Sender<StdioSendBuf>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ServiceStatus {
+    NotStarted,
+    /// The service waits for its dependencies before it is started
+    Waiting,
+    /// The `pre_start` hooks run and the command is spawned
+    Starting,
+    Running,
+    /// The service was asked to stop and is waited for to exit
+    Stopping,
+    Exited,
+    /// A oneshot service exited successfully
+    Completed,
+    Failed(i32),
+    /// The child process couldn't be spawned
+    FailedToStart,
+    Killed,
+}
+
+mod error {
+    use std::fmt::{Display, Formatter};
+    use std::io;
+    use std::sync::PoisonError;
+
+    pub type SmResult = Result<(), SmError>;
+
+    pub enum SmError {
+        Io(io::Error),
+        MutexPoisoned,
+        FailedToSendStdio,
+        InvalidConfig(String),
+        /// Everything that is wrong with the config, one problem per entry
+        ConfigProblems(Vec<String>),
+        /// This should never happen and would be a panic in most programs, but panicking here
+        /// is not a good idea
+        Bug(&'static str),
+    }
+
+    impl From<io::Error> for SmError {
+        fn from(e: io::Error) -> Self {
+            Self::Io(e)
+        }
+    }
+
+    impl<T> From<std::sync::PoisonError<T>> for SmError {
+        fn from(_: PoisonError<T>) -> Self {
+            Self::MutexPoisoned
+        }
+    }
+
+    impl Display for SmError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Io(e) => Display::fmt(e, f),
+                SmError::MutexPoisoned => f.write_str("Mutex was poisoned. This is a bug."),
+                SmError::FailedToSendStdio => {
+                    f.write_str("Failed to send stdio to display thread. This is a bug.")
+                }
+                SmError::InvalidConfig(msg) => write!(f, "Invalid config: {}", msg),
+                SmError::ConfigProblems(problems) => {
+                    f.write_str("Invalid config:")?;
+                    for problem in problems {
+                        write!(f, "\n  {}", problem)?;
+                    }
+                    Ok(())
+                }
+                SmError::Bug(str) => write!(f, "{}. This is a bug.", str),
+            }
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use std::time::{Duration, SystemTime};
+
+/// How many steps the search for the next run of a cron expression takes at most. Every step
+/// skips at least a minute that doesn't match, a whole day if the day doesn't match.
+const MAX_SEARCH_STEPS: usize = 100_000;
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// When a service is run periodically while the service manager is open
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// A cron expression, which is matched against the local time
+    Cron(Cron),
+    /// A fixed time between the runs, counted from when the service manager was opened
+    Every(Duration),
+}
+
+/// The five fields of a cron expression. Every field is a set of the values that match, stored
+/// as bits.
+#[derive(Debug, Clone)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Like cron, a day matches if either the day of the month or the weekday matches when both
+    /// are restricted
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Schedule {
+    /// Parses the `schedule` and `every` of a service, of which at most one can be set
+    pub fn from_config(
+        schedule: Option<&str>,
+        every: Option<&str>,
+    ) -> Result<Option<Schedule>, String> {
+        match (schedule, every) {
+            (Some(_), Some(_)) => Err("`schedule` and `every` can't be used together".to_string()),
+            (Some(schedule), None) => {
+                let cron = schedule.parse::<Cron>()?;
+                if cron.next_after(SystemTime::now()).is_none() {
+                    return Err(format!("`{}` never matches", schedule));
+                }
+                Ok(Some(Schedule::Cron(cron)))
+            }
+            (None, Some(every)) => parse_duration(every).map(|every| Some(Schedule::Every(every))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// The first time after `time` that the service has to run
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        match self {
+            Schedule::Cron(cron) => cron.next_after(time),
+            Schedule::Every(every) => Some(time + *every),
+        }
+    }
+}
+
+impl Cron {
+    /// The first full minute after `time` that matches. `None` if there is none in the next
+    /// years, which happens for days that don't exist like `0 0 30 2 *`.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let secs = time.duration_since(SystemTime::UNIX_EPOCH).ok()?.as_secs();
+        let mut minute = (secs / 60 + 1) * 60;
+
+        for _ in 0..MAX_SEARCH_STEPS {
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(minute);
+            let tm = local_time(time);
+
+            let skip = if !self.day_matches(&tm) {
+                // to the next midnight
+                86_400 - (tm.tm_hour as u64 * 3600 + tm.tm_min as u64 * 60)
+            } else if !has(self.hours, tm.tm_hour) {
+                3600 - tm.tm_min as u64 * 60
+            } else if !has(self.minutes, tm.tm_min) {
+                60
+            } else {
+                return Some(time);
+            };
+
+            minute += skip;
+        }
+
+        None
+    }
+
+    fn day_matches(&self, tm: &libc::tm) -> bool {
+        if !has(self.months, tm.tm_mon + 1) {
+            return false;
+        }
+
+        let day = has(self.days, tm.tm_mday);
+        let weekday = has(self.weekdays, tm.tm_wday);
+
+        if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+}
+
+impl std::str::FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+
+        let (minutes, hours, days, months, weekdays) = match fields[..] {
+            [minutes, hours, days, months, weekdays] => (minutes, hours, days, months, weekdays),
+            _ => {
+                return Err(format!(
+                    "`{}` has to have five fields: minute, hour, day of month, month and weekday",
+                    s
+                ))
+            }
+        };
+
+        // Sunday is both 0 and 7
+        let mut weekday_bits = parse_field(weekdays, "weekday", 0, 7, &WEEKDAY_NAMES, 0)?;
+        if has(weekday_bits, 7) {
+            weekday_bits |= 1;
+        }
+
+        Ok(Cron {
+            minutes: parse_field(minutes, "minute", 0, 59, &[], 0)?,
+            hours: parse_field(hours, "hour", 0, 23, &[], 0)?,
+            days: parse_field(days, "day of month", 1, 31, &[], 0)?,
+            months: parse_field(months, "month", 1, 12, &MONTH_NAMES, 1)?,
+            weekdays: weekday_bits,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        })
+    }
+}
+
+/// Parses a field like `*/15`, `1-5`, `mon-fri` or `0,30`. `names` can be used instead of the
+/// numbers starting at `first_name`.
+fn parse_field(
+    field: &str,
+    kind: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    first_name: u32,
+) -> Result<u64, String> {
+    let invalid = || format!("invalid {} `{}`", kind, field);
+
+    let value = |value: &str| -> Result<u32, String> {
+        let parsed = match names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(value))
+        {
+            Some(index) => index as u32 + first_name,
+            None => value.parse().map_err(|_| invalid())?,
+        };
+
+        if (min..=max).contains(&parsed) {
+            Ok(parsed)
+        } else {
+            Err(format!(
+                "{} `{}` has to be between {} and {}",
+                kind, value, min, max
+            ))
+        }
+    };
+
+    let mut bits = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(invalid()),
+            },
+            None => (part, None),
+        };
+
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            // `5/10` means every 10th starting at 5
+            None if step.is_some() => (value(range)?, max),
+            None => {
+                let value = value(range)?;
+                (value, value)
+            }
+        };
+
+        if start > end {
+            return Err(invalid());
+        }
+
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+/// Parses durations like `30s`, `10m` or `1h30m`
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "invalid duration `{}`, expected something like `30s`, `10m` or `1h30m`",
+            s
+        )
+    };
+
+    let mut secs = 0u64;
+    let mut number = String::new();
+
+    for c in s.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let value = number.parse::<u64>().map_err(|_| invalid())?;
+        secs = value
+            .checked_mul(unit)
+            .and_then(|value| secs.checked_add(value))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+
+    if !number.is_empty() || secs == 0 {
+        return Err(invalid());
+    }
+
+    Ok(Duration::from_secs(secs))
+}
+
+fn has(bits: u64, value: libc::c_int) -> bool {
+    (0..64).contains(&value) && bits & (1 << value) != 0
+}
+
+/// Converts the time to the local timezone
+pub fn local_time(time: SystemTime) -> libc::tm {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0) as libc::time_t;
+
+    // SAFETY: `localtime_r` only writes to the `tm` that is passed to it, which is valid for
+    // writes. An all-zero `tm` is a valid value.
+    unsafe {
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        libc::localtime_r(&secs, &mut tm);
+        tm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has, local_time, parse_duration, Cron, Schedule};
+    use std::time::{Duration, SystemTime};
+
+    /// The values whose bits are set
+    fn values(bits: u64) -> Vec<i32> {
+        (0..64).filter(|value| has(bits, *value)).collect()
+    }
+
+    fn cron(expression: &str) -> Cron {
+        expression.parse().unwrap()
+    }
+
+    /// Some time that isn't on a full minute
+    fn start() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_123)
+    }
+
+    #[test]
+    fn ranges() {
+        let cron = cron("0-4 9-17 1-3 6-8 1-5");
+        assert_eq!(values(cron.minutes), [0, 1, 2, 3, 4]);
+        assert_eq!(values(cron.hours), (9..=17).collect::<Vec<_>>());
+        assert_eq!(values(cron.days), [1, 2, 3]);
+        assert_eq!(values(cron.months), [6, 7, 8]);
+        assert_eq!(values(cron.weekdays), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn steps() {
+        assert_eq!(values(cron("*/15 * * * *").minutes), [0, 15, 30, 45]);
+        assert_eq!(values(cron("5/20 * * * *").minutes), [5, 25, 45]);
+        assert_eq!(values(cron("10-20/5 * * * *").minutes), [10, 15, 20]);
+        assert_eq!(values(cron("* */6 * * *").hours), [0, 6, 12, 18]);
+        assert_eq!(values(cron("* * */10 * *").days), [1, 11, 21, 31]);
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(values(cron("1,3,5 * * * *").minutes), [1, 3, 5]);
+        assert_eq!(
+            values(cron("0,10-12,*/30 * * * *").minutes),
+            [0, 10, 11, 12, 30]
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(values(cron("* * * jan,DEC *").months), [1, 12]);
+        assert_eq!(values(cron("* * * * mon-fri").weekdays), [1, 2, 3, 4, 5]);
+        assert_eq!(values(cron("* * * * Sun").weekdays), [0]);
+    }
+
+    #[test]
+    fn sunday_is_0_and_7() {
+        assert_eq!(values(cron("* * * * 7").weekdays), [0, 7]);
+        assert_eq!(values(cron("* * * * 5-7").weekdays), [0, 5, 6, 7]);
+    }
+
+    #[test]
+    fn invalid_fields() {
+        for expression in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "1,,2 * * * *",
+            "* * * foo *",
+        ] {
+            assert!(expression.parse::<Cron>().is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn next_run_is_the_next_matching_minute() {
+        let start = start();
+        let next = cron("*/15 * * * *").next_after(start).unwrap();
+        let tm = local_time(next);
+
+        assert!(next > start);
+        assert!(next.duration_since(start).unwrap() <= Duration::from_secs(15 * 60));
+        assert_eq!(tm.tm_min % 15, 0);
+        assert_eq!(tm.tm_sec, 0);
+    }
+
+    #[test]
+    fn next_run_is_after_a_matching_time() {
+        let first = cron("* * * * *").next_after(start()).unwrap();
+        let second = cron("* * * * *").next_after(first).unwrap();
+        assert_eq!(
+            second.duration_since(first).unwrap(),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn day_of_month_or_weekday() {
+        // both restricted: the 13th and every Friday
+        let cron = cron("0 0 13 * fri");
+        let mut time = start();
+        let mut saw_friday_not_13th = false;
+
+        for _ in 0..20 {
+            time = cron.next_after(time).unwrap();
+            let tm = local_time(time);
+            assert!(tm.tm_mday == 13 || tm.tm_wday == 5);
+            assert_eq!((tm.tm_hour, tm.tm_min), (0, 0));
+            saw_friday_not_13th |= tm.tm_mday != 13;
+        }
+
+        assert!(saw_friday_not_13th);
+    }
+
+    #[test]
+    fn day_of_month_and_any_weekday() {
+        // only the day of the month is restricted, so the weekday doesn't matter
+        let cron = cron("0 0 13 * *");
+        let mut time = start();
+
+        for _ in 0..5 {
+            time = cron.next_after(time).unwrap();
+            assert_eq!(local_time(time).tm_mday, 13);
+        }
+    }
+
+    #[test]
+    fn days_that_dont_exist() {
+        assert!(cron("0 0 30 2 *").next_after(start()).is_none());
+        assert!(Schedule::from_config(Some("0 0 30 2 *"), None).is_err());
+        assert!(cron("0 0 31 4,6 *").next_after(start()).is_none());
+    }
+
+    #[test]
+    fn schedule_or_every() {
+        assert!(Schedule::from_config(Some("* * * * *"), Some("1m")).is_err());
+        assert!(Schedule::from_config(None, None).unwrap().is_none());
+        assert!(matches!(
+            Schedule::from_config(None, Some("10m")),
+            Ok(Some(Schedule::Every(every))) if every == Duration::from_secs(600)
+        ));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(2 * 86_400)));
+        assert_eq!(parse_duration(" 1m30s "), Ok(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        for duration in [
+            "",
+            "10",
+            "m",
+            "5x",
+            "0s",
+            "1h 30m",
+            "-5s",
+            "99999999999999999999s",
+        ] {
+            assert!(parse_duration(duration).is_err(), "{}", duration);
+        }
+    }
+
+    #[test]
+    fn local_time_moves_with_the_time() {
+        let start = start();
+        let tm = local_time(start);
+        let later = local_time(start + Duration::from_secs(60));
+
+        // time zones are offset by whole minutes
+        assert_eq!(tm.tm_sec, 1_700_000_123 % 60);
+        assert_eq!(later.tm_sec, tm.tm_sec);
+        assert_eq!(later.tm_min, (tm.tm_min + 1) % 60);
+        assert!((0..12).contains(&tm.tm_mon));
+        assert!((0..7).contains(&tm.tm_wday));
+    }
+}
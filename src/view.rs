@@ -1,15 +1,25 @@
 use std::fmt::{Display, Formatter};
+use std::time::SystemTime;
 use tui::backend::Backend;
-use tui::layout::{Constraint, Layout, Rect};
+use tui::layout::{Constraint, Direction, Layout, Rect};
 use tui::style::{Color, Modifier, Style};
-use tui::text::Spans;
-use tui::widgets::{Block, Borders, Cell, Paragraph, Row, Table};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState};
 use tui::Frame;
 
-use crate::model::{AppState, ServiceStatus};
+use crate::model::keymap::Action;
+use crate::model::schedule;
+use crate::model::{
+    AppState, Details, Hitboxes, MergedView, Output, OutputLine, Run, Service, ServiceStatus,
+    SplitLayout, SplitView, Tab, TableRow, Usage,
+};
 use crate::App;
 
+const HINT_SPACING: &str = "    ";
+
 pub fn render_ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    app.hitboxes = Hitboxes::default();
+
     let chunks = if f.size().height < 22 {
         Layout::default()
             .constraints(vec![Constraint::Percentage(100)])
@@ -20,44 +30,406 @@ pub fn render_ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
             .split(f.size())
     };
 
-    match app.selected {
-        None => {
-            render_table(f, &mut app.table, chunks[0]);
-        }
-        Some(index) => {
-            render_full_view(f, &mut app.table, index, chunks[0]);
+    if let Some(merged) = &app.merged {
+        render_merged_view(f, &app.table, merged, chunks[0]);
+    } else if let Some(split) = &mut app.split {
+        render_split_view(f, &app.table, split, &mut app.hitboxes.panes, chunks[0]);
+    } else {
+        match app.selected {
+            None => {
+                render_table(f, &mut app.table, &mut app.hitboxes.rows, chunks[0]);
+            }
+            Some(index) => {
+                let area = match &app.details {
+                    Some(details) => {
+                        let service = &app.table.services[index];
+                        render_details(f, service, details, chunks[0])
+                    }
+                    None => chunks[0],
+                };
+
+                render_full_view(f, &app.table, index, app.viewed_run, &mut app.scroll, area);
+            }
         }
     }
 
     if let Some(footer_chunk) = chunks.get(1) {
-        render_help_footer(f, app, *footer_chunk);
+        app.hitboxes.hints = render_help_footer(f, app, *footer_chunk);
     }
 }
 
-fn render_full_view<B: Backend>(f: &mut Frame<B>, state: &mut AppState, index: usize, area: Rect) {
+fn render_full_view<B: Backend>(
+    f: &mut Frame<B>,
+    state: &AppState,
+    index: usize,
+    viewed_run: Option<usize>,
+    scroll: &mut usize,
+    area: Rect,
+) {
     let service = &state.services[index];
 
-    let block = Block::default()
-        .borders(Borders::ALL)
-        .title("service".as_ref());
+    let run_index = viewed_run.unwrap_or_else(|| service.runs.len().saturating_sub(1));
+    let run = service.runs.get(run_index);
 
-    let len = service.std_io_buf.len();
-    let stdout = if len > 10000 {
-        String::from_utf8_lossy(&service.std_io_buf[len - 10000..len])
-    } else {
-        String::from_utf8_lossy(&service.std_io_buf)
+    let block = Block::default().borders(Borders::ALL);
+
+    let stdout = output_tail(run.map(|run| &run.output), block.inner(area).height, scroll);
+
+    let title = match run {
+        Some(run) => format!(
+            "{} - run {}/{}, {}",
+            service.name,
+            run_index + 1,
+            service.runs.len(),
+            run_summary(run)
+        ),
+        None => service.name.clone(),
     };
+    let block = block.title(scroll_title(&title, *scroll));
 
-    let paragraph = Paragraph::new(stdout.as_ref()).block(block.clone());
+    let paragraph = Paragraph::new(stdout).block(block);
 
     f.render_widget(paragraph, area)
 }
 
-fn render_table<B: Backend>(f: &mut Frame<B>, state: &mut AppState, area: Rect) {
+fn render_split_view<B: Backend>(
+    f: &mut Frame<B>,
+    state: &AppState,
+    split: &mut SplitView,
+    pane_hitboxes: &mut Vec<(Rect, usize)>,
+    area: Rect,
+) {
+    let direction = match split.layout {
+        SplitLayout::Tiled => Direction::Horizontal,
+        SplitLayout::Stacked => Direction::Vertical,
+    };
+
+    let pane_count = split.panes.len() as u32;
+    let chunks = Layout::default()
+        .direction(direction)
+        .constraints(
+            split
+                .panes
+                .iter()
+                .map(|_| Constraint::Ratio(1, pane_count))
+                .collect::<Vec<_>>(),
+        )
+        .split(area);
+
+    for (i, (pane, chunk)) in split.panes.iter_mut().zip(chunks).enumerate() {
+        pane_hitboxes.push((chunk, i));
+
+        let service = &state.services[pane.service];
+
+        let border_style = if i == split.focused {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default()
+        };
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(border_style);
+
+        let stdout = output_tail(
+            current_output(service),
+            block.inner(chunk).height,
+            &mut pane.scroll,
+        );
+
+        let block = block.title(scroll_title(&service.name, pane.scroll));
+
+        let paragraph = Paragraph::new(stdout).block(block);
+
+        f.render_widget(paragraph, chunk);
+    }
+}
+
+fn scroll_title(name: &str, scroll: usize) -> String {
+    if scroll == 0 {
+        name.to_string()
+    } else {
+        format!("{} (scrolled up {})", name, scroll)
+    }
+}
+
+/// Renders the details of a service at the top of `area`, returning the rest of the area
+fn render_details<B: Backend>(
+    f: &mut Frame<B>,
+    service: &Service,
+    details: &Details,
+    area: Rect,
+) -> Rect {
+    let mut lines = vec![
+        Spans::from(format!("command: {}", service.command)),
+        Spans::from(format!("workdir: {}", service.workdir.display())),
+    ];
+
+    if !service.env_files.is_empty() {
+        let env_files = service
+            .env_files
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        lines.push(Spans::from(format!("env files: {}", env_files)));
+    }
+
+    if let Some(usage) = &details.usage {
+        lines.push(Spans::from(format!("usage: {}", usage)));
+    }
+
+    lines.push(Spans::from("environment:"));
+
+    match &details.env {
+        Ok(env) => lines.extend(env.iter().map(|(name, value)| {
+            let value = if is_secret(name) { "********" } else { value };
+            Spans::from(format!("  {}={}", name, value))
+        })),
+        Err(err) => lines.push(Spans::from(Span::styled(
+            format!("  {}", err),
+            Style::default().fg(Color::Red),
+        ))),
+    }
+
+    let chunks = Layout::default()
+        .constraints(vec![
+            Constraint::Length(lines.len() as u16 + 2),
+            Constraint::Min(3),
+        ])
+        .split(area);
+
+    let paragraph =
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("details"));
+
+    f.render_widget(paragraph, chunks[0]);
+
+    chunks[1]
+}
+
+impl Display for Usage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.memory, self.memory_peak) {
+            (Some(memory), Some(peak)) => {
+                write!(f, "memory {} (peak {}), ", size(memory), size(peak))?
+            }
+            (Some(memory), None) => write!(f, "memory {}, ", size(memory))?,
+            _ => write!(f, "memory not accounted, ")?,
+        }
+
+        match self.cpu {
+            Some(cpu) => write!(f, "cpu {:.2}s", cpu.as_secs_f64()),
+            None => write!(f, "cpu not accounted"),
+        }
+    }
+}
+
+/// A number of bytes in the largest unit that keeps it above 1, like `12.3M`
+fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+/// Whether the value of an environment variable looks like something that shouldn't be shown
+fn is_secret(name: &str) -> bool {
+    const SECRET_PARTS: [&str; 7] = [
+        "SECRET",
+        "TOKEN",
+        "PASSWORD",
+        "PASSWD",
+        "KEY",
+        "CREDENTIAL",
+        "AUTH",
+    ];
+
+    let name = name.to_uppercase();
+    SECRET_PARTS.iter().any(|part| name.contains(part))
+}
+
+/// When a run started and how it ended
+fn run_summary(run: &Run) -> String {
+    match run.exit {
+        Some((exited, status)) => format!(
+            "started {}, {} at {}",
+            clock_time(run.started),
+            status,
+            clock_time(exited)
+        ),
+        None => format!("started {}", clock_time(run.started)),
+    }
+}
+
+/// The output of the current run of a service
+fn current_output(service: &Service) -> Option<&Output> {
+    service.runs.back().map(|run| &run.output)
+}
+
+/// Returns the last lines of an output that fit into `height`, skipping the last `scroll` lines.
+/// `scroll` is clamped so that it can't be scrolled past the first line.
+fn output_tail(output: Option<&Output>, height: u16, scroll: &mut usize) -> String {
+    let output = match output {
+        Some(output) => output,
+        None => return String::new(),
+    };
+
+    let height = height as usize;
+    let line_count = output.iter().count();
+
+    *scroll = (*scroll).min(line_count.saturating_sub(height));
+
+    let end = line_count - *scroll;
+    let start = end.saturating_sub(height);
+
+    output
+        .iter()
+        .skip(start)
+        .take(end - start)
+        .map(|line| line.text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn render_merged_view<B: Backend>(
+    f: &mut Frame<B>,
+    state: &AppState,
+    merged: &MergedView,
+    area: Rect,
+) {
+    let chunks = Layout::default()
+        .constraints(vec![Constraint::Length(3), Constraint::Min(0)])
+        .split(area);
+
+    let filter = state
+        .services
+        .iter()
+        .enumerate()
+        .flat_map(|(i, service)| {
+            let mut style = if merged.excluded.contains(&i) {
+                Style::default().add_modifier(Modifier::DIM | Modifier::CROSSED_OUT)
+            } else {
+                Style::default().fg(service_color(i))
+            };
+
+            if i == merged.cursor {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+
+            [Span::styled(service.name.as_str(), style), Span::raw("  ")]
+        })
+        .collect::<Vec<_>>();
+
+    let filter_bar = Paragraph::new(Spans::from(filter))
+        .block(Block::default().borders(Borders::ALL).title("services"));
+
+    f.render_widget(filter_bar, chunks[0]);
+
+    let block = Block::default().borders(Borders::ALL).title("all services");
+    let height = block.inner(chunks[1]).height as usize;
+
+    let name_width = state
+        .services
+        .iter()
+        .map(|service| service.name.len())
+        .max()
+        .unwrap_or(0);
+
+    // every service's output is already sorted by time, so walking all of them backwards and
+    // always taking the newest line gives the last lines of the interleaved stream
+    let mut iters = state
+        .services
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !merged.excluded.contains(i))
+        .filter_map(|(i, service)| Some((i, current_output(service)?.iter().rev().peekable())))
+        .collect::<Vec<_>>();
+
+    let mut lines: Vec<(usize, &OutputLine)> = Vec::with_capacity(height);
+
+    while lines.len() < height {
+        let newest = iters
+            .iter_mut()
+            .filter_map(|(i, iter)| iter.peek().map(|line| (*i, line.time)))
+            .max_by_key(|(_, time)| *time);
+
+        match newest {
+            Some((index, _)) => {
+                let iter = iters
+                    .iter_mut()
+                    .find(|(i, _)| *i == index)
+                    .map(|(_, iter)| iter);
+
+                if let Some(line) = iter.and_then(|iter| iter.next()) {
+                    lines.push((index, line));
+                }
+            }
+            None => break,
+        }
+    }
+
+    let text = lines
+        .into_iter()
+        .rev()
+        .map(|(index, line)| {
+            let name = &state.services[index].name;
+            Spans::from(vec![
+                Span::styled(
+                    format!("{:width$} | ", name, width = name_width),
+                    Style::default().fg(service_color(index)),
+                ),
+                Span::raw(line.text.as_str()),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    let paragraph = Paragraph::new(text).block(block);
+
+    f.render_widget(paragraph, chunks[1]);
+}
+
+/// A color that is used to tell the output of different services apart
+fn service_color(index: usize) -> Color {
+    const COLORS: [Color; 6] = [
+        Color::Cyan,
+        Color::Yellow,
+        Color::Green,
+        Color::Magenta,
+        Color::Blue,
+        Color::Red,
+    ];
+
+    COLORS[index % COLORS.len()]
+}
+
+fn render_table<B: Backend>(
+    f: &mut Frame<B>,
+    state: &mut AppState,
+    row_hitboxes: &mut Vec<(Rect, usize)>,
+    area: Rect,
+) {
     let selected_style = Style::default().add_modifier(Modifier::REVERSED);
     let normal_style = Style::default().bg(Color::Blue);
-    let header_cells = ["Name", "Status"]
+
+    // the column is only shown if it isn't empty
+    let scheduled = state
+        .services
         .iter()
+        .any(|service| state.is_in_tab(service) && service.schedule.is_some());
+
+    let header_cells = ["Name", "Status", "Next run"]
+        .iter()
+        .take(if scheduled { 3 } else { 2 })
         .map(|h| Cell::from(*h).style(Style::default()));
 
     let header = Row::new(header_cells)
@@ -65,56 +437,287 @@ fn render_table<B: Backend>(f: &mut Frame<B>, state: &mut AppState, area: Rect)
         .height(1)
         .bottom_margin(1);
 
-    let rows = state.services.iter().map(|service| {
-        let height = service.name.chars().filter(|c| *c == '\n').count() + 1;
-
-        let cells = [
-            Cell::from(service.name.as_ref()),
-            Cell::from(
-                service
-                    .status
-                    .lock()
-                    .expect("service.status lock poisoned") // returning result here is too much effort
-                    .to_string(),
-            ),
-        ];
-        Row::new(cells).height(height as u16).bottom_margin(1)
-    });
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(table_title(state));
+    let inner = block.inner(area);
+
+    // the header and its margin
+    let rows_y = inner.y + 2;
+    let rows_height = inner.height.saturating_sub(2);
+
+    let table_rows = state.rows();
+
+    // every row is followed by a margin of one line
+    let row_heights = table_rows
+        .iter()
+        .map(|row| match row {
+            TableRow::Group(_) => 2,
+            TableRow::Service(index) => {
+                let name = &state.services[*index].name;
+                name.chars().filter(|c| *c == '\n').count() as u16 + 2
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // the table is scrolled here instead of in tui so that we know which rows are visible
+    let selected = state
+        .table_state
+        .selected()
+        .map(|selected| selected.min(table_rows.len().saturating_sub(1)));
+    if let Some(selected) = selected {
+        state.table_offset = state.table_offset.min(selected);
+
+        while state.table_offset < selected
+            && row_heights[state.table_offset..=selected]
+                .iter()
+                .sum::<u16>()
+                > rows_height
+        {
+            state.table_offset += 1;
+        }
+    }
+    state.table_offset = state.table_offset.min(table_rows.len().saturating_sub(1));
+
+    let mut y = rows_y;
+    for (index, height) in row_heights.iter().enumerate().skip(state.table_offset) {
+        if y >= rows_y + rows_height {
+            break;
+        }
+
+        let row_area = Rect::new(inner.x, y, inner.width, *height - 1);
+        row_hitboxes.push((row_area.intersection(inner), index));
+        y += height;
+    }
+
+    let rows = table_rows
+        .iter()
+        .zip(&row_heights)
+        .skip(state.table_offset)
+        .map(|(row, height)| {
+            let cells = match row {
+                TableRow::Group(group) => group_cells(state, group).to_vec(),
+                TableRow::Service(index) => {
+                    let service = &state.services[*index];
+
+                    let mut name = service.name.clone();
+                    if service.marked {
+                        name = format!("* {}", name);
+                    }
+                    if service.group.is_some() {
+                        name = format!("  {}", name);
+                    }
+
+                    let mut cells = vec![
+                        Cell::from(name),
+                        Cell::from(
+                            service
+                                .status
+                                .lock()
+                                .expect("service.status lock poisoned") // returning result here is too much effort
+                                .to_string(),
+                        ),
+                    ];
+                    if let Some(next_run) = service.next_run {
+                        cells.push(Cell::from(next_run_time(next_run)));
+                    }
+                    cells
+                }
+            };
+            Row::new(cells).height(height - 1).bottom_margin(1)
+        });
 
     let t = Table::new(rows)
         .header(header)
-        .block(Block::default().borders(Borders::ALL).title("services"))
+        .block(block)
         .highlight_style(selected_style)
-        .widths(&[Constraint::Percentage(50), Constraint::Length(30)]);
+        .widths(&[
+            Constraint::Percentage(50),
+            Constraint::Length(30),
+            Constraint::Length(12),
+        ]);
+
+    let mut table_state = TableState::default();
+    table_state.select(selected.map(|selected| selected - state.table_offset));
 
-    f.render_stateful_widget(t, area, &mut state.table_state);
+    f.render_stateful_widget(t, area, &mut table_state);
 }
 
-fn render_help_footer<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
+/// When a scheduled run starts, as the time of day if it is within the next day
+fn next_run_time(time: SystemTime) -> String {
+    let days = time
+        .duration_since(SystemTime::now())
+        .map(|duration| duration.as_secs() / (24 * 60 * 60))
+        .unwrap_or(0);
+
+    match days {
+        0 => clock_time(time),
+        1 => "in 1 day".to_string(),
+        days => format!("in {} days", days),
+    }
+}
+
+/// The tabs of the table with the current one highlighted, if there are tasks
+fn table_title(state: &AppState) -> Spans<'static> {
+    if !state.has_tasks() {
+        return Spans::from("services");
+    }
+
+    let tab = |name, tab| {
+        if state.tab == tab {
+            Span::styled(name, Style::default().add_modifier(Modifier::REVERSED))
+        } else {
+            Span::raw(name)
+        }
+    };
+
+    Spans::from(vec![
+        tab("services", Tab::Services),
+        Span::raw(" | "),
+        tab("tasks", Tab::Tasks),
+    ])
+}
+
+fn group_cells<'a>(state: &AppState, group: &str) -> [Cell<'a>; 2] {
+    let services = state.group_services(group);
+    let running = services
+        .iter()
+        .filter(|index| {
+            *state.services[**index]
+                .status
+                .lock()
+                .expect("service.status lock poisoned")
+                == ServiceStatus::Running
+        })
+        .count();
+
+    let arrow = if state.collapsed.contains(group) {
+        '▶'
+    } else {
+        '▼'
+    };
+
+    let style = Style::default().add_modifier(Modifier::BOLD);
+
+    [
+        Cell::from(format!("{} {}", arrow, group)).style(style),
+        Cell::from(format!("{}/{} running", running, services.len())).style(style),
+    ]
+}
+
+/// Renders the hints for the keys of the current view, returning where each of them was drawn
+fn render_help_footer<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) -> Vec<(Rect, Action)> {
     let block = Block::default().title("help").borders(Borders::ALL);
 
-    let paragraph = Paragraph::new(if app.is_table() {
-        vec![Spans::from(
-            "q-quit    down-down    up-up    enter-select    r-run service    k-kill service",
-        )]
+    let hints: &[(Action, &str)] = if app.is_table() {
+        &[
+            (Action::Quit, "quit"),
+            (Action::Down, "down"),
+            (Action::Up, "up"),
+            (Action::Top, "top"),
+            (Action::Bottom, "bottom"),
+            (Action::Select, "select"),
+            (Action::Run, "run service"),
+            (Action::Kill, "kill service"),
+            (Action::Restart, "restart service"),
+            (Action::RunAll, "run all"),
+            (Action::KillAll, "kill all"),
+            (Action::Mark, "mark"),
+            (Action::Split, "split marked"),
+            (Action::Merged, "all logs"),
+            (Action::Tasks, "services/tasks"),
+        ]
+    } else if app.split.is_some() {
+        &[
+            (Action::Quit, "back"),
+            (Action::NextPane, "next pane"),
+            (Action::Up, "scroll up"),
+            (Action::Down, "scroll down"),
+            (Action::ToggleLayout, "toggle layout"),
+            (Action::Run, "run service"),
+            (Action::Kill, "kill service"),
+        ]
+    } else if app.merged.is_some() {
+        &[
+            (Action::Quit, "back"),
+            (Action::Left, "previous service"),
+            (Action::Right, "next service"),
+            (Action::ToggleFilter, "show/hide service"),
+        ]
     } else {
-        vec![Spans::from(
-            "q-back    esc-back    r-run service    k-kill service",
-        )]
-    })
-    .block(block);
+        &[
+            (Action::Quit, "back"),
+            (Action::Back, "back"),
+            (Action::Up, "scroll up"),
+            (Action::Down, "scroll down"),
+            (Action::Top, "top"),
+            (Action::Bottom, "bottom"),
+            (Action::Details, "details"),
+            (Action::PreviousRun, "previous run"),
+            (Action::NextRun, "next run"),
+            (Action::Run, "run service"),
+            (Action::Kill, "kill service"),
+            (Action::Restart, "restart service"),
+        ]
+    };
+
+    let hints = hints
+        .iter()
+        .filter(|(action, _)| *action != Action::Tasks || app.table.has_tasks())
+        .filter_map(|(action, description)| {
+            app.keymap
+                .key(*action)
+                .map(|key| (*action, format!("{}-{}", key, description)))
+        })
+        .collect::<Vec<_>>();
+
+    let inner = block.inner(area);
+    let mut x = inner.x;
+    let mut hitboxes = Vec::new();
+    for (action, hint) in &hints {
+        // hints that don't fit are cut off and can't be clicked
+        if x >= inner.right() {
+            break;
+        }
+
+        let width = hint.chars().count() as u16;
+        hitboxes.push((Rect::new(x, inner.y, width, 1).intersection(inner), *action));
+        x = x.saturating_add(width + HINT_SPACING.len() as u16);
+    }
+
+    let help = hints
+        .into_iter()
+        .map(|(_, hint)| hint)
+        .collect::<Vec<_>>()
+        .join(HINT_SPACING);
+
+    let paragraph = Paragraph::new(vec![Spans::from(help)]).block(block);
 
     f.render_widget(paragraph, area);
+
+    hitboxes
 }
 
 impl Display for ServiceStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
+            ServiceStatus::Waiting => f.write_str("waiting"),
+            ServiceStatus::Starting => f.write_str("starting"),
             ServiceStatus::Running => f.write_str("running"),
+            ServiceStatus::Stopping => f.write_str("stopping"),
             ServiceStatus::Exited => f.write_str("exited (0)"),
+            ServiceStatus::Completed => f.write_str("completed ✓"),
             ServiceStatus::Failed(code) => write!(f, "failed ({})", code),
             ServiceStatus::NotStarted => f.write_str("not started"),
+            ServiceStatus::FailedToStart => f.write_str("failed to start"),
             ServiceStatus::Killed => f.write_str("killed"),
         }
     }
 }
+
+/// Formats the time of day in the local timezone as `HH:MM:SS`
+pub fn clock_time(time: SystemTime) -> String {
+    let tm = schedule::local_time(time);
+
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}